@@ -0,0 +1,330 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use crate::config::{config, MarketConfig};
+use crate::report::relative;
+use crate::strategy::{self, Position};
+
+const USAGE: &str = "Usage: kraken_trading_bot backtest <file.csv|file.json> [--quote 1.0] [--base 0.0] [--fee 0.0016]";
+
+/**
+ * One price observation of the configured market. CSV files need a header
+ * naming the columns: timestamp,relative,base_fiat,quote_fiat
+ * (eth_xbt,eth_eur,xbt_eur are accepted for BTC/ETH price files)
+ */
+#[derive(Debug, Deserialize)]
+pub struct Tick {
+    pub timestamp: String,
+    // base/quote
+    #[serde(alias = "eth_xbt")]
+    pub relative: Decimal,
+    #[serde(alias = "eth_eur")]
+    pub base_fiat: Decimal,
+    #[serde(alias = "xbt_eur")]
+    pub quote_fiat: Decimal,
+}
+
+struct Settings {
+    file: String,
+    quote: Decimal,
+    base: Decimal,
+    fee: Decimal,
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    ticks: usize,
+    hops: usize,
+    quote: Decimal,
+    base: Decimal,
+    fees_fiat: Decimal,
+    max_drawdown_fiat: Decimal,
+    max_drawdown_quote: Decimal,
+    final_value_fiat: Decimal,
+    final_value_quote: Decimal,
+    hold_value_fiat: Decimal,
+    hold_value_quote: Decimal,
+}
+
+/**
+ * kraken_trading_bot backtest <file.csv|file.json> [--quote 1.0] [--base 0.0] [--fee 0.0016]
+ * Thresholds come from the config, so `--set strategy.to_quote=...` tries out new values.
+ */
+pub fn run(args: &[String]) {
+    let settings = match parse_args(args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            println!("[BACKTEST] {}", e);
+            println!("{}", USAGE);
+            return;
+        }
+    };
+
+    let ticks = match load_ticks(&settings.file) {
+        Ok(ticks) => ticks,
+        Err(e) => {
+            println!("[BACKTEST] Could not load {}: {}", settings.file, e);
+            return;
+        }
+    };
+    if ticks.is_empty() {
+        println!("[BACKTEST] {} contains no prices", settings.file);
+        return;
+    }
+
+    let report = simulate(&ticks, &settings);
+    print_report(&settings, &ticks, &report);
+}
+
+fn parse_args(args: &[String]) -> Result<Settings, Error> {
+    let mut settings = Settings {
+        file: String::new(),
+        quote: Decimal::ONE,
+        base: Decimal::ZERO,
+        fee: Decimal::new(16, 4),
+    };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let target = match arg.as_str() {
+            "--quote" | "--btc" => &mut settings.quote,
+            "--base" | "--eth" => &mut settings.base,
+            "--fee" => &mut settings.fee,
+            file if settings.file.is_empty() && !file.starts_with("--") => {
+                settings.file = file.to_string();
+                continue;
+            },
+            other => return Err(Error::new(ErrorKind::InvalidInput, format!("Unknown argument {}", other))),
+        };
+        *target = match iter.next().map(|value| value.parse::<Decimal>()) {
+            Some(Ok(value)) if !value.is_sign_negative() => value,
+            _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{} expects a non-negative number", arg))),
+        };
+    }
+    if settings.file.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "No price file given"));
+    }
+    Ok(settings)
+}
+
+pub fn load_ticks(file: &str) -> Result<Vec<Tick>, Error> {
+    if file.ends_with(".json") {
+        return serde_json::from_str(&fs::read_to_string(file)?).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()));
+    }
+    parse_csv(&fs::read_to_string(file)?)
+}
+
+fn parse_csv(content: &str) -> Result<Vec<Tick>, Error> {
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = match lines.next() {
+        Some(header) => header.split(',').map(|column| column.trim()).collect(),
+        None => return Ok(vec![]),
+    };
+    let column = |name: &str, legacy: &str| header.iter().position(|c| *c == name || *c == legacy)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Missing column {}", name)));
+    let (ts, relative, base_fiat, quote_fiat) = (
+        column("timestamp", "timestamp")?,
+        column("relative", "eth_xbt")?,
+        column("base_fiat", "eth_eur")?,
+        column("quote_fiat", "xbt_eur")?,
+    );
+
+    let mut ticks = vec![];
+    for (line_number, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        let number = |index: usize| fields.get(index)
+            .and_then(|field| field.parse::<Decimal>().ok())
+            .filter(|value| value.is_sign_positive() && !value.is_zero())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Bad price on data line {}", line_number + 1)));
+        ticks.push(Tick {
+            timestamp: fields.get(ts).unwrap_or(&"").to_string(),
+            relative: number(relative)?,
+            base_fiat: number(base_fiat)?,
+            quote_fiat: number(quote_fiat)?,
+        });
+    }
+    Ok(ticks)
+}
+
+fn position_of(quote: Decimal, base: Decimal, tick: &Tick) -> Position {
+    let quote_fiat = quote * tick.quote_fiat;
+    let base_fiat = base * tick.base_fiat;
+    if quote_fiat.is_zero() && base_fiat.is_zero() {
+        Position::None
+    } else if quote_fiat > base_fiat {
+        Position::Quote
+    } else {
+        Position::Base
+    }
+}
+
+/**
+ * Orders fill at the tick price, same as the limit order the bot places
+ */
+fn simulate(ticks: &[Tick], settings: &Settings) -> Report {
+    let mut report = Report {
+        ticks: ticks.len(),
+        quote: settings.quote,
+        base: settings.base,
+        ..Default::default()
+    };
+    let mut last_value = ticks[0].relative;
+    let mut peak_fiat = Decimal::ZERO;
+    let mut peak_quote = Decimal::ZERO;
+    let thresholds = config().strategy;
+
+    for tick in ticks {
+        let position = position_of(report.quote, report.base, tick);
+        let gain = strategy::gain(&position, tick.relative, last_value).unwrap_or(Decimal::ZERO);
+
+        if strategy::should_hop(&position, strategy::net_gain(gain, settings.fee), &thresholds) {
+            match position {
+                Position::Quote => {
+                    report.fees_fiat += report.quote * settings.fee * tick.quote_fiat;
+                    report.base += report.quote * (Decimal::ONE - settings.fee) / tick.relative;
+                    report.quote = Decimal::ZERO;
+                },
+                Position::Base => {
+                    report.fees_fiat += report.base * settings.fee * tick.base_fiat;
+                    report.quote += report.base * (Decimal::ONE - settings.fee) * tick.relative;
+                    report.base = Decimal::ZERO;
+                },
+                Position::None => (),
+            }
+            last_value = tick.relative;
+            report.hops += 1;
+        }
+
+        let value_fiat = report.quote * tick.quote_fiat + report.base * tick.base_fiat;
+        let value_quote = report.quote + report.base * tick.relative;
+        peak_fiat = peak_fiat.max(value_fiat);
+        peak_quote = peak_quote.max(value_quote);
+        if let Some(ratio) = value_fiat.checked_div(peak_fiat) {
+            report.max_drawdown_fiat = report.max_drawdown_fiat.max(Decimal::ONE - ratio);
+        }
+        if let Some(ratio) = value_quote.checked_div(peak_quote) {
+            report.max_drawdown_quote = report.max_drawdown_quote.max(Decimal::ONE - ratio);
+        }
+    }
+
+    let last = &ticks[ticks.len() - 1];
+    report.final_value_fiat = report.quote * last.quote_fiat + report.base * last.base_fiat;
+    report.final_value_quote = report.quote + report.base * last.relative;
+    report.hold_value_fiat = settings.quote * last.quote_fiat + settings.base * last.base_fiat;
+    report.hold_value_quote = settings.quote + settings.base * last.relative;
+    report
+}
+
+fn print_report(settings: &Settings, ticks: &[Tick], report: &Report) {
+    let market: MarketConfig = config().market;
+    let (quote, base, fiat) = (&market.quote, &market.base, &market.fiat);
+    println!(
+        "BACKTEST {} {} ({} ticks, {} -> {})",
+        market.pair(),
+        settings.file,
+        report.ticks,
+        ticks[0].timestamp,
+        ticks[ticks.len() - 1].timestamp,
+    );
+    println!("START {:<10}{:.5}", format!("{}:", quote), settings.quote.round_dp(5));
+    println!("START {:<10}{:.5}", format!("{}:", base), settings.base.round_dp(5));
+    println!("FINAL {:<10}{:.5}", format!("{}:", quote), report.quote.round_dp(5));
+    println!("FINAL {:<10}{:.5}", format!("{}:", base), report.base.round_dp(5));
+    println!("HOPS:           {}", report.hops);
+    println!("FEES PAID:      {:.2} {} ({:.2}% per hop)", report.fees_fiat.round_dp(2), fiat, (settings.fee * Decimal::ONE_HUNDRED).round_dp(2));
+    println!(
+        "MAX DRAWDOWN:   {:.2}% in {}, {:.2}% in {}",
+        (report.max_drawdown_fiat * Decimal::ONE_HUNDRED).round_dp(2), fiat, (report.max_drawdown_quote * Decimal::ONE_HUNDRED).round_dp(2), quote,
+    );
+    println!("STRATEGY VALUE: {:.2} {} / {:.5} {}", report.final_value_fiat.round_dp(2), fiat, report.final_value_quote.round_dp(5), quote);
+    println!("HOLD VALUE:     {:.2} {} / {:.5} {}", report.hold_value_fiat.round_dp(2), fiat, report.hold_value_quote.round_dp(5), quote);
+    println!(
+        "VS HOLD:        {} in {}, {} in {}",
+        relative(report.final_value_fiat, report.hold_value_fiat),
+        fiat,
+        relative(report.final_value_quote, report.hold_value_quote),
+        quote,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    const FIXTURE: &str = "timestamp,relative,base_fiat,quote_fiat
+2024-01-01T00:00:00Z,0.05,3000,60000
+
+2024-01-01T00:01:00Z, 0.051 ,3060,60000
+";
+
+    fn tick(relative: Decimal) -> Tick {
+        let quote_fiat = dec!(60000);
+        Tick { timestamp: String::new(), relative, base_fiat: relative * quote_fiat, quote_fiat }
+    }
+
+    fn settings(fee: Decimal) -> Settings {
+        Settings { file: String::new(), quote: Decimal::ONE, base: Decimal::ZERO, fee }
+    }
+
+    #[test]
+    fn parses_csv_prices() {
+        let ticks = parse_csv(FIXTURE).unwrap();
+        assert_eq!(ticks.len(), 2);
+        assert_eq!(ticks[0].timestamp, "2024-01-01T00:00:00Z");
+        assert_eq!(ticks[1].relative, dec!(0.051));
+        assert_eq!(ticks[1].base_fiat, dec!(3060));
+        assert_eq!(ticks[1].quote_fiat, dec!(60000));
+    }
+
+    #[test]
+    fn accepts_the_legacy_columns_in_any_order() {
+        let ticks = parse_csv("xbt_eur,eth_xbt,timestamp,eth_eur\n60000,0.05,now,3000\n").unwrap();
+        assert_eq!(ticks[0].relative, dec!(0.05));
+        assert_eq!(ticks[0].base_fiat, dec!(3000));
+        assert_eq!(ticks[0].timestamp, "now");
+    }
+
+    #[test]
+    fn rejects_malformed_rows() {
+        let malformed = format!("{}2024-01-01T00:02:00Z,abc,3000,60000\n", FIXTURE);
+        let error = parse_csv(&malformed).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Bad price on data line 3");
+        let negative = format!("{}2024-01-01T00:02:00Z,0.05,-1,60000\n", FIXTURE);
+        assert!(parse_csv(&negative).is_err());
+        let short = format!("{}2024-01-01T00:02:00Z,0.05\n", FIXTURE);
+        assert!(parse_csv(&short).is_err());
+        assert_eq!(parse_csv("timestamp,relative,quote_fiat\n").unwrap_err().to_string(), "Missing column base_fiat");
+        assert!(parse_csv("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn hops_once_the_gain_passes_the_threshold() {
+        // default thresholds: 2% to the base asset, 3% back to the quote asset
+        let at_threshold = [tick(dec!(0.05)), tick(dec!(0.049))];
+        assert_eq!(simulate(&at_threshold, &settings(Decimal::ZERO)).hops, 0);
+
+        let ticks = [
+            tick(dec!(0.05)),
+            tick(dec!(0.0489)),
+            tick(dec!(0.050367)),
+            tick(dec!(0.0504)),
+        ];
+        let report = simulate(&ticks, &settings(Decimal::ZERO));
+        assert_eq!(report.hops, 2);
+        assert_eq!(report.base, Decimal::ZERO);
+        assert_eq!(report.quote.round_dp(8), (dec!(0.0504) / dec!(0.0489)).round_dp(8));
+    }
+
+    #[test]
+    fn hops_pay_the_fee() {
+        let fee = dec!(0.0016);
+        let report = simulate(&[tick(dec!(0.05)), tick(dec!(0.048))], &settings(fee));
+        assert_eq!(report.hops, 1);
+        assert_eq!(report.quote, Decimal::ZERO);
+        assert_eq!(report.base, (Decimal::ONE - fee) / dec!(0.048));
+        assert_eq!(report.fees_fiat, fee * dec!(60000));
+    }
+}
@@ -0,0 +1,672 @@
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use std::{env, fmt, fs};
+
+/**
+ * Runtime configuration, loaded once at startup
+ */
+pub static CONFIG: Lazy<RwLock<Config>> = Lazy::new(|| RwLock::new(Config::default()));
+// file the config was loaded from, live changes are written back to it
+static CONFIG_FILE: OnceCell<String> = OnceCell::new();
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const ENV_PREFIX: &str = "KRAKEN_BOT_";
+// depths supported by Kraken book subscriptions
+const BOOK_DEPTHS: [usize; 5] = [10, 25, 100, 500, 1000];
+// keys from before the strategy worked on arbitrary pairs
+const LEGACY_KEYS: [(&str, &str); 2] = [("strategy.to_btc", "strategy.to_quote"), ("strategy.to_eth", "strategy.to_base")];
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub strategy: StrategyConfig,
+    pub market: MarketConfig,
+    pub pricing: PricingConfig,
+    pub fees: FeesConfig,
+    pub timers: TimersConfig,
+    pub errors: ErrorsConfig,
+    pub reconnect: ReconnectConfig,
+    pub telegram: TelegramConfig,
+    pub files: FilesConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StrategyConfig {
+    // gain needed to hop from the base asset back to the quote asset
+    #[serde(alias = "to_btc")]
+    pub to_quote: f64,
+    // gain needed to hop from the quote asset to the base asset
+    #[serde(alias = "to_eth")]
+    pub to_base: f64,
+    pub partial_fill: PartialFillPolicy,
+    // hops are proposed on Telegram and only placed once approved
+    pub approval: bool,
+}
+
+/**
+ * What to do with the rest of a hop order that only partially filled
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartialFillPolicy {
+    // place a new limit order for the remainder at the current price
+    Requote,
+    // complete the remainder with a market order
+    Market,
+    // keep the split balance
+    Accept,
+}
+
+/**
+ * The two assets hopped between. They need a direct pair
+ * (base/quote) and a pair each against the fiat reference.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarketConfig {
+    // asset codes as used in websocket pair names
+    pub base: String,
+    pub quote: String,
+    pub fiat: String,
+    // keys of the assets in the balance response
+    pub base_balance_key: String,
+    pub quote_balance_key: String,
+    // pair name used when placing orders
+    pub order_pair: String,
+    // precision and minimum order of order_pair, only used
+    // when Kraken's AssetPairs can't be reached
+    pub price_decimals: u32,
+    pub lot_decimals: u32,
+    pub ordermin: f64,
+    pub book_depth: usize,
+    // follow own orders on Kraken's private websocket instead of only polling them
+    pub private_feed: bool,
+}
+
+impl MarketConfig {
+    pub fn pair(&self) -> String {
+        format!("{}/{}", self.base, self.quote)
+    }
+
+    pub fn base_fiat_pair(&self) -> String {
+        format!("{}/{}", self.base, self.fiat)
+    }
+
+    pub fn quote_fiat_pair(&self) -> String {
+        format!("{}/{}", self.quote, self.fiat)
+    }
+
+    /**
+     * Order books subscribed to on the websocket
+     */
+    pub fn pairs(&self) -> Vec<String> {
+        vec![self.pair(), self.base_fiat_pair(), self.quote_fiat_pair()]
+    }
+}
+
+/**
+ * How the limit price of a hop is derived from the order book
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PricingConfig {
+    pub policy: PricingPolicy,
+    // ticks past the other side of the book for the cross policy
+    pub cross_ticks: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PricingPolicy {
+    // best bid when buying, best ask when selling
+    Join,
+    // past the best price of the other side
+    Cross,
+    // middle of the spread
+    Mid,
+    // volume weighted price of the other side over market.book_depth levels
+    Vwap,
+}
+
+/**
+ * Trading fees as fractions, e.g. 0.0016 for 0.16%
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeesConfig {
+    // ask Kraken for the account's fee tier, maker and taker are the fallback
+    pub fetch: bool,
+    pub maker: f64,
+    pub taker: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimersConfig {
+    // balance refresh while no order is pending
+    pub poll_interval_ms: u64,
+    // order status checks while an order is waiting to be filled
+    pub order_poll_interval_ms: u64,
+    // how often the websocket books are read for changes
+    pub book_sample_ms: u64,
+    // book changes within this window are evaluated once
+    pub debounce_ms: u64,
+    // between two hop decisions
+    pub min_decision_interval_ms: u64,
+    // trading is suspended when a book has not changed for this long. The
+    // connection itself is watched by the heartbeat, this only catches a book
+    // that stopped updating, so it has to outlast quiet pairs
+    pub book_stale_secs: u64,
+    // replaced by [reconnect], still accepted so older files load
+    pub reconnect_timer_secs: Option<u64>,
+    // in order poll iterations
+    pub order_abort_timeout: u64,
+}
+
+/**
+ * Retry and alerting of failing exchange calls
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorsConfig {
+    // first backoff, doubled on every consecutive failure
+    pub retry_backoff_ms: u64,
+    pub retry_backoff_max_ms: u64,
+    // consecutive transient failures before alerting on Telegram
+    pub alert_after: u32,
+}
+
+/**
+ * Reconnect schedule of the websockets
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    // first delay, doubled on every failed attempt and jittered
+    pub backoff_ms: u64,
+    pub backoff_max_ms: u64,
+    // the bot stops when a stream can't be reconnected within this window
+    pub window_secs: u64,
+}
+
+/**
+ * Who may command the bot, the report chat is always allowed
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    // chats commands are accepted from besides the report chat
+    pub allowed_chats: Vec<i64>,
+    // senders allowed in those chats, empty allows every member
+    pub allowed_users: Vec<i64>,
+    // a /hop preview can be confirmed for this long
+    pub confirm_timeout_secs: u64,
+    // a hop proposal in approval mode can be answered for this long,
+    // and no new one is made until it passed
+    pub approval_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilesConfig {
+    pub creds: String,
+    // last.json and last_completed.json next to it are migrated on first start
+    pub state: String,
+    // SQLite trade journal
+    pub journal: String,
+    // starting balance of --mock runs
+    pub mock_balance: String,
+    // balances of --paper runs, kept between runs
+    pub paper_balance: String,
+    // prices replayed by --mock runs, in the backtest format
+    pub mock_prices: String,
+}
+
+impl Default for StrategyConfig {
+    fn default() -> Self {
+        StrategyConfig {
+            to_quote: 0.03,
+            to_base: 0.02,
+            partial_fill: PartialFillPolicy::Requote,
+            approval: false,
+        }
+    }
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        MarketConfig {
+            base: "ETH".to_string(),
+            quote: "XBT".to_string(),
+            fiat: "EUR".to_string(),
+            base_balance_key: "XETH".to_string(),
+            quote_balance_key: "XXBT".to_string(),
+            order_pair: "ETH/BTC".to_string(),
+            price_decimals: 5,
+            lot_decimals: 8,
+            ordermin: 0.,
+            book_depth: 10,
+            private_feed: true,
+        }
+    }
+}
+
+impl Default for PricingConfig {
+    fn default() -> Self {
+        PricingConfig {
+            policy: PricingPolicy::Mid,
+            cross_ticks: 1,
+        }
+    }
+}
+
+impl Default for FeesConfig {
+    fn default() -> Self {
+        FeesConfig {
+            fetch: true,
+            maker: 0.0016,
+            taker: 0.0026,
+        }
+    }
+}
+
+impl Default for TimersConfig {
+    fn default() -> Self {
+        TimersConfig {
+            poll_interval_ms: 5000,
+            order_poll_interval_ms: 30000,
+            book_sample_ms: 100,
+            debounce_ms: 250,
+            min_decision_interval_ms: 5000,
+            book_stale_secs: 900,
+            reconnect_timer_secs: None,
+            order_abort_timeout: 60,
+        }
+    }
+}
+
+impl Default for ErrorsConfig {
+    fn default() -> Self {
+        ErrorsConfig {
+            retry_backoff_ms: 5000,
+            retry_backoff_max_ms: 300000,
+            alert_after: 3,
+        }
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            backoff_ms: 1000,
+            backoff_max_ms: 60000,
+            window_secs: 1800,
+        }
+    }
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        TelegramConfig {
+            allowed_chats: vec![],
+            allowed_users: vec![],
+            confirm_timeout_secs: 60,
+            approval_timeout_secs: 300,
+        }
+    }
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        FilesConfig {
+            creds: "./creds.json".to_string(),
+            state: "state.json".to_string(),
+            journal: "journal.sqlite".to_string(),
+            mock_balance: "mock_balance.json".to_string(),
+            paper_balance: "paper_balance.json".to_string(),
+            mock_prices: "mock_prices.csv".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Write(String, std::io::Error),
+    Argument(String),
+    UnknownKey(String),
+    InvalidValue(String, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(file, e) => write!(f, "could not read {}: {}", file, e),
+            ConfigError::Parse(file, e) => write!(f, "could not parse {}: {}", file, e),
+            ConfigError::Write(file, e) => write!(f, "could not write {}: {}", file, e),
+            ConfigError::Argument(arg) => write!(f, "invalid argument: {}", arg),
+            ConfigError::UnknownKey(key) => write!(f, "unknown config key '{}'", key),
+            ConfigError::InvalidValue(key, value) => write!(f, "invalid value '{}' for '{}'", value, key),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl Config {
+    /**
+     * Reads a TOML file. The default file is optional, an explicitly
+     * requested one is not.
+     */
+    pub fn from_file(file: &str, required: bool) -> Result<Config, ConfigError> {
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(ConfigError::Read(file.to_string(), e)),
+        };
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(file.to_string(), e))
+    }
+
+    /**
+     * Sets a value by its dotted key, e.g. `strategy.to_quote`.
+     * Lists are given comma separated.
+     */
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let key = canonical_key(key);
+        let invalid = || ConfigError::InvalidValue(key.to_string(), value.to_string());
+        let mut root = toml::Value::try_from(&*self).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        let slot = key.split_once('.')
+            .and_then(|(section, field)| root.get_mut(section).and_then(|s| s.get_mut(field)))
+            .ok_or_else(|| ConfigError::UnknownKey(key.to_string()))?;
+
+        *slot = match slot {
+            toml::Value::Float(_) => toml::Value::Float(value.parse().map_err(|_| invalid())?),
+            toml::Value::Integer(_) => toml::Value::Integer(value.parse().map_err(|_| invalid())?),
+            toml::Value::Boolean(_) => toml::Value::Boolean(value.parse().map_err(|_| invalid())?),
+            toml::Value::Array(_) => toml::Value::Array(
+                value.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| item.parse().map(toml::Value::Integer).unwrap_or_else(|_| toml::Value::String(item.to_string())))
+                    .collect()
+            ),
+            _ => toml::Value::String(value.to_string()),
+        };
+        *self = root.try_into().map_err(|_| invalid())?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| Err(ConfigError::Invalid(reason.to_string()));
+        for (name, threshold) in [("strategy.to_quote", self.strategy.to_quote), ("strategy.to_base", self.strategy.to_base)] {
+            if !(threshold > 0. && threshold < 1.) {
+                return invalid(&format!("{} must be between 0 and 1, got {}", name, threshold));
+            }
+        }
+        let market = &self.market;
+        let codes = [&market.base, &market.quote, &market.fiat, &market.base_balance_key, &market.quote_balance_key, &market.order_pair];
+        if codes.iter().any(|code| code.is_empty() || code.contains(char::is_whitespace)) {
+            return invalid("market asset codes, balance keys and order_pair must be set and contain no spaces");
+        }
+        if market.base == market.quote || market.base == market.fiat || market.quote == market.fiat {
+            return invalid("market.base, market.quote and market.fiat must be different assets");
+        }
+        if market.price_decimals > 12 || market.lot_decimals > 12 {
+            return invalid("market.price_decimals and market.lot_decimals must be at most 12");
+        }
+        if market.ordermin < 0. {
+            return invalid(&format!("market.ordermin must not be negative, got {}", market.ordermin));
+        }
+        if !BOOK_DEPTHS.contains(&self.market.book_depth) {
+            return invalid(&format!("market.book_depth must be one of {:?}, got {}", BOOK_DEPTHS, self.market.book_depth));
+        }
+        for (name, fee) in [("fees.maker", self.fees.maker), ("fees.taker", self.fees.taker)] {
+            if !(0. ..0.1).contains(&fee) {
+                return invalid(&format!("{} must be at least 0 and below 0.1, got {}", name, fee));
+            }
+        }
+        if self.timers.poll_interval_ms == 0 || self.timers.order_poll_interval_ms == 0 {
+            return invalid("timers.poll_interval_ms and timers.order_poll_interval_ms must be positive");
+        }
+        if self.timers.book_sample_ms == 0 || self.timers.book_stale_secs == 0 {
+            return invalid("timers.book_sample_ms and timers.book_stale_secs must be positive");
+        }
+        if self.timers.order_abort_timeout == 0 {
+            return invalid("timers.order_abort_timeout must be positive");
+        }
+        if self.errors.retry_backoff_ms == 0 || self.errors.retry_backoff_max_ms < self.errors.retry_backoff_ms {
+            return invalid("errors.retry_backoff_ms must be positive and at most errors.retry_backoff_max_ms");
+        }
+        if self.errors.alert_after == 0 {
+            return invalid("errors.alert_after must be positive");
+        }
+        if self.reconnect.backoff_ms == 0 || self.reconnect.backoff_max_ms < self.reconnect.backoff_ms {
+            return invalid("reconnect.backoff_ms must be positive and at most reconnect.backoff_max_ms");
+        }
+        if self.reconnect.window_secs == 0 {
+            return invalid("reconnect.window_secs must be positive");
+        }
+        if self.telegram.confirm_timeout_secs == 0 || self.telegram.approval_timeout_secs == 0 {
+            return invalid("telegram.confirm_timeout_secs and telegram.approval_timeout_secs must be positive");
+        }
+        let files = &self.files;
+        if [&files.creds, &files.state, &files.journal, &files.mock_balance, &files.paper_balance, &files.mock_prices]
+            .iter()
+            .any(|file| file.is_empty())
+        {
+            return invalid("files.* must not be empty");
+        }
+        Ok(())
+    }
+
+    /**
+     * KRAKEN_BOT_<SECTION>_<KEY>=value, e.g. KRAKEN_BOT_STRATEGY_TO_BTC=0.04
+     */
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        let mut vars: Vec<(String, String)> = env::vars()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX) && name != "KRAKEN_BOT_CONFIG")
+            .collect();
+        vars.sort();
+        for (name, value) in vars {
+            let key = match name[ENV_PREFIX.len()..].to_lowercase().split_once('_') {
+                Some((section, field)) => format!("{}.{}", section, field),
+                None => return Err(ConfigError::UnknownKey(name)),
+            };
+            self.set(&key, &value)?;
+        }
+        Ok(())
+    }
+}
+
+fn canonical_key(key: &str) -> &str {
+    LEGACY_KEYS.iter().find(|(legacy, _)| *legacy == key).map_or(key, |(_, canonical)| canonical)
+}
+
+/**
+ * Loads the config file (`--config <file>`, $KRAKEN_BOT_CONFIG or config.toml),
+ * applies env and `--set key=value` overrides and validates the result.
+ * Returns the arguments that were not meant for the config.
+ */
+pub fn init(args: &[String]) -> Result<Vec<String>, ConfigError> {
+    let mut file = env::var("KRAKEN_BOT_CONFIG").ok();
+    let mut overrides = vec![];
+    let mut rest = vec![];
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" => file = Some(iter.next().ok_or_else(|| ConfigError::Argument("--config expects a file".to_string()))?.clone()),
+            "--set" => {
+                let assignment = iter.next().ok_or_else(|| ConfigError::Argument("--set expects key=value".to_string()))?;
+                match assignment.split_once('=') {
+                    Some((key, value)) => overrides.push((key.to_string(), value.to_string())),
+                    None => return Err(ConfigError::Argument(format!("--set {}", assignment))),
+                }
+            },
+            _ => rest.push(arg.clone()),
+        }
+    }
+
+    let mut config = match &file {
+        Some(file) => Config::from_file(file, true)?,
+        None => Config::from_file(DEFAULT_CONFIG_FILE, false)?,
+    };
+    let _ = CONFIG_FILE.set(file.unwrap_or_else(|| DEFAULT_CONFIG_FILE.to_string()));
+    config.apply_env()?;
+    for (key, value) in overrides {
+        config.set(&key, &value)?;
+    }
+    config.validate()?;
+
+    *CONFIG.write().unwrap() = config;
+    Ok(rest)
+}
+
+pub fn config() -> Config {
+    CONFIG.read().unwrap().clone()
+}
+
+/**
+ * Changes a strategy value of the running bot, e.g. `to_eth` or `strategy.to_base`.
+ * The result is validated like at startup and written to the config file.
+ * Returns the canonical key and its old and new value.
+ */
+pub fn set_live(key: &str, value: &str) -> Result<(String, String, String), ConfigError> {
+    let key = if key.contains('.') { key.to_string() } else { format!("strategy.{}", key) };
+    let key = canonical_key(&key).to_string();
+    let field = match key.split_once('.') {
+        Some(("strategy", field)) => field.to_string(),
+        _ => return Err(ConfigError::Argument(format!("only strategy values can be changed live, not {}", key))),
+    };
+
+    let mut current = CONFIG.write().unwrap();
+    let mut updated = current.clone();
+    updated.set(&key, value)?;
+    updated.validate()?;
+    let strategy_value = |config: &Config| {
+        toml::Value::try_from(&config.strategy).ok().and_then(|strategy| strategy.get(&field).cloned())
+    };
+    let (old, new) = match (strategy_value(&current), strategy_value(&updated)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return Err(ConfigError::UnknownKey(key)),
+    };
+    let file = CONFIG_FILE.get().map(String::as_str).unwrap_or(DEFAULT_CONFIG_FILE);
+    write_value(file, "strategy", &field, &new)?;
+    *current = updated;
+    let display = |value: toml::Value| match value {
+        toml::Value::String(text) => text,
+        other => other.to_string(),
+    };
+    Ok((key, display(old), display(new)))
+}
+
+/**
+ * Updates one key of the config file in place, keeping its comments.
+ * A key the file gives by its legacy name keeps that name.
+ */
+fn write_value(file: &str, section: &str, field: &str, value: &toml::Value) -> Result<(), ConfigError> {
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(ConfigError::Read(file.to_string(), e)),
+    };
+    let mut document: toml_edit::DocumentMut = content
+        .parse()
+        .map_err(|e| ConfigError::Invalid(format!("could not edit {}: {}", file, e)))?;
+    let table = document
+        .entry(section)
+        .or_insert_with(toml_edit::table)
+        .as_table_mut()
+        .ok_or_else(|| ConfigError::Invalid(format!("{} in {} is not a table", section, file)))?;
+    let key = format!("{}.{}", section, field);
+    let name = LEGACY_KEYS
+        .iter()
+        .filter(|(_, canonical)| *canonical == key)
+        .filter_map(|(legacy, _)| legacy.split_once('.').map(|(_, legacy_field)| legacy_field))
+        .find(|legacy_field| table.contains_key(legacy_field))
+        .unwrap_or(field);
+    let mut new = match value {
+        toml::Value::Float(float) => toml_edit::Value::from(*float),
+        toml::Value::Integer(integer) => toml_edit::Value::from(*integer),
+        toml::Value::Boolean(boolean) => toml_edit::Value::from(*boolean),
+        other => toml_edit::Value::from(other.as_str().unwrap_or_default()),
+    };
+    match table.get_mut(name).and_then(|item| item.as_value_mut()) {
+        Some(existing) => {
+            *new.decor_mut() = existing.decor().clone();
+            *existing = new;
+        },
+        None => table[name] = toml_edit::Item::Value(new),
+    }
+
+    let tmp = format!("{}.tmp", file);
+    fs::write(&tmp, document.to_string())
+        .and_then(|_| fs::rename(&tmp, file))
+        .map_err(|e| ConfigError::Write(file.to_string(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a file per test, tests run in parallel
+    fn scratch_file(name: &str, content: &str) -> String {
+        let file = std::env::temp_dir().join(format!("kraken_trading_bot_config_{}_{}.toml", name, std::process::id()));
+        fs::write(&file, content).unwrap();
+        file.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn sets_a_float() {
+        let mut config = Config::default();
+        config.set("strategy.to_base", "0.015").unwrap();
+        assert_eq!(config.strategy.to_base, 0.015);
+        config.set("strategy.to_eth", "0.025").unwrap();
+        assert_eq!(config.strategy.to_base, 0.025);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let mut config = Config::default();
+        assert!(matches!(config.set("strategy.to_moon", "0.1"), Err(ConfigError::UnknownKey(_))));
+        assert!(matches!(config.set("to_base", "0.1"), Err(ConfigError::UnknownKey(_))));
+        assert!(matches!(config.set("nosection.to_base", "0.1"), Err(ConfigError::UnknownKey(_))));
+    }
+
+    #[test]
+    fn rejects_bad_and_out_of_range_values() {
+        let mut config = Config::default();
+        assert!(matches!(config.set("strategy.to_base", "lots"), Err(ConfigError::InvalidValue(_, _))));
+        assert_eq!(config.strategy.to_base, Config::default().strategy.to_base);
+        for out_of_range in ["1.5", "0", "-0.01"] {
+            let mut updated = Config::default();
+            updated.set("strategy.to_quote", out_of_range).unwrap();
+            assert!(matches!(updated.validate(), Err(ConfigError::Invalid(_))), "{} was accepted", out_of_range);
+        }
+    }
+
+    #[test]
+    fn writes_a_legacy_key_in_place() {
+        let content = "# hop thresholds\n[strategy]\n# gain to go back to XBT\nto_btc = 0.03 # three percent\nto_base = 0.02\n\n[market]\nbase = \"ETH\"\n";
+        let file = scratch_file("legacy", content);
+        write_value(&file, "strategy", "to_quote", &toml::Value::Float(0.04)).unwrap();
+        let written = fs::read_to_string(&file).unwrap();
+        assert_eq!(written, content.replace("to_btc = 0.03", "to_btc = 0.04"));
+        assert!(!written.contains("to_quote"));
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn writes_a_missing_key_into_its_section() {
+        let file = scratch_file("missing", "[strategy]\n# kept\nto_base = 0.02\n");
+        write_value(&file, "strategy", "approval", &toml::Value::Boolean(true)).unwrap();
+        let written = fs::read_to_string(&file).unwrap();
+        assert!(written.starts_with("[strategy]\n# kept\nto_base = 0.02\n"));
+        assert!(written.contains("approval = true"));
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,751 @@
+use rust_decimal::Decimal;
+use krakenrs::ws::{BookData, KrakenWsAPI};
+use krakenrs::TxId;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use teloxide::utils::markdown;
+use teloxide::Bot;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, Instant, MissedTickBehavior};
+
+use crate::config::config;
+use crate::error::{BotError, Retry};
+use crate::journal::Event;
+use crate::mock_feed::MockFeed;
+use crate::pricing::{BookAges, BookProblem};
+use crate::proposal::{self, Kind, Proposal};
+use crate::strategy::{self, Position};
+use crate::reconnect::Reconnect;
+use crate::state::OrderSide;
+use crate::telegram::{
+    self, notify_reconnect_failed, notify_reconnect_gave_up, notify_stream_close, notify_successful_reconnect,
+    send_telegram_message,
+};
+use crate::{
+    abort_order, abort_order_command, book_problems, calculate_gain, calculate_net_gain, check_last_order, ensure_reference_price,
+    execute_hop, get_account_balance, get_my_position, hop_order, is_waiting_order_resolution, journal_hop, journal_placement,
+    pair_info, pending_txid, resolve_last_order, set_book_problem, setup_private_ws, setup_ws, threshold_reason, time, trading_mode,
+    trading_paused, update_last_order, update_prices, TradingMode,
+};
+
+/**
+ * Requests to the order manager, the only task placing or cancelling orders
+ */
+pub enum OrderCommand {
+    Hop(HopRequest),
+    // answered with the MarkdownV2 reply for the chat
+    Abort(oneshot::Sender<String>),
+    // the private feed saw the order leave the book
+    OrderClosed(TxId),
+}
+
+/**
+ * A hop someone decided on. It is journaled as a decision only once the
+ * order manager accepts it, a refusal is journaled with its reason.
+ */
+pub struct HopRequest {
+    pub position: Position,
+    pub gain: Decimal,
+    pub net_gain: Decimal,
+    // why the hop was decided
+    pub reason: String,
+    // a hop decided on the thresholds must still clear them when placed
+    pub recheck: bool,
+    // the order a confirmed preview showed, placed as is
+    pub order: Option<(OrderSide, Decimal, Decimal)>,
+    // manual hops are answered with the MarkdownV2 outcome for the chat
+    pub reply: Option<oneshot::Sender<String>>,
+}
+
+type Balance = HashMap<String, Decimal>;
+
+/**
+ * Runs a blocking exchange or file call off the async workers
+ */
+pub async fn blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.expect("blocking task panicked")
+}
+
+/**
+ * Dropping a websocket joins its thread, which waits for the stream
+ * to shut down, so it is closed off the async workers
+ */
+async fn close_ws(ws: KrakenWsAPI) {
+    blocking(move || drop(ws)).await;
+}
+
+/**
+ * Starts market data, strategy, order manager and Telegram on this runtime
+ * and runs them until SIGINT or SIGTERM, or until one of them stops.
+ * Without Telegram (a --mock run) notifications are only logged.
+ */
+pub async fn run(telegram: bool) {
+    let bot = telegram.then(Bot::from_env);
+    let (stop_notifier, notifier_stopped) = oneshot::channel();
+    let notifier = bot.clone().map(|bot| {
+        let messages = telegram::setup_notifier();
+        tokio::spawn(telegram::run_notifier(bot, messages, notifier_stopped))
+    });
+
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let (deltas_sender, deltas) = watch::channel(());
+    let (balances_sender, balances) = watch::channel(None);
+    let (orders_sender, orders) = mpsc::channel(8);
+
+    let mut tasks = vec![
+        ("market data", tokio::spawn(market_data(deltas_sender, shutdown.clone()))),
+        ("strategy", tokio::spawn(run_strategy(deltas, balances, orders_sender.clone(), shutdown.clone()))),
+        ("order manager", tokio::spawn(order_manager(orders, balances_sender, shutdown.clone()))),
+        ("order feed", tokio::spawn(order_feed(orders_sender.clone(), shutdown.clone()))),
+    ];
+    if let Some(bot) = bot {
+        tasks.push(("telegram", tokio::spawn(telegram::run_commands(bot, orders_sender, shutdown))));
+    }
+
+    let failed = tokio::select! {
+        _ = shutdown_signal() => false,
+        name = first_stopped(&mut tasks) => {
+            println!("[{} | SHUTDOWN] {} task stopped", time(), name);
+            true
+        },
+    };
+    let _ = shutdown_sender.send(true);
+    for (name, task) in tasks {
+        if let Err(e) = task.await {
+            println!("[{} | SHUTDOWN] {} task failed: {}", time(), name, e);
+        }
+    }
+    let pending = if is_waiting_order_resolution() { " The pending order stays on the book." } else { "" };
+    send_telegram_message(markdown::escape(&format!("Bot stopped. 🛑{}", pending)));
+    let _ = stop_notifier.send(());
+    if let Some(notifier) = notifier {
+        let _ = notifier.await;
+    }
+    println!("[{} | SHUTDOWN] Stopped", time());
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("could not listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => println!("[{} | SHUTDOWN] SIGINT received", time()),
+        _ = terminate.recv() => println!("[{} | SHUTDOWN] SIGTERM received", time()),
+    }
+}
+
+/**
+ * Name of the first task to finish, the others keep running
+ */
+async fn first_stopped(tasks: &mut Vec<(&'static str, JoinHandle<()>)>) -> &'static str {
+    loop {
+        if let Some(index) = tasks.iter().position(|(_, task)| task.is_finished()) {
+            let (name, task) = tasks.remove(index);
+            if let Err(e) = task.await {
+                println!("[{} | SHUTDOWN] {} task failed: {}", time(), name, e);
+            }
+            return name;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/**
+ * Connects, retrying on the reconnect schedule. None on shutdown
+ * or once the retry window is over.
+ */
+async fn connect<T: Send + 'static>(
+    name: &str,
+    setup: fn() -> Result<T, String>,
+    reconnect: &mut Reconnect,
+    shutdown: &mut watch::Receiver<bool>,
+    alert: bool,
+) -> Option<T> {
+    loop {
+        let error = match blocking(setup).await {
+            Ok(connection) => return Some(connection),
+            Err(e) => e,
+        };
+        let delay = match reconnect.next_delay(&config().reconnect) {
+            Some(delay) => delay,
+            None => {
+                println!("[{} | RECONNECT] {} gave up after {} attempts: {}", time(), name, reconnect.attempts(), error);
+                return None;
+            },
+        };
+        println!("[{} | RECONNECT] {} attempt {} failed: {}, next in {:?}", time(), name, reconnect.attempts(), error, delay);
+        if alert {
+            notify_reconnect_failed(name, reconnect.attempts(), &error, delay);
+        }
+        tokio::select! {
+            _ = shutdown.changed() => return None,
+            _ = sleep(delay) => (),
+        }
+    }
+}
+
+/**
+ * Reads the books off the websocket, publishes every change as a delta,
+ * flags books that stopped changing and reconnects when the stream closes
+ * or every book went quiet. krakenrs watches the heartbeat and closes the
+ * stream when Kraken stops answering, but it doesn't tell when the last
+ * message arrived, so book changes are all we can time here.
+ */
+async fn market_data(deltas: watch::Sender<()>, mut shutdown: watch::Receiver<bool>) {
+    if trading_mode() == TradingMode::Mock {
+        return mock_market_data(deltas, shutdown).await;
+    }
+    let timers = config().timers;
+    let pairs = config().market.pairs();
+    let stale_after = Duration::from_secs(timers.book_stale_secs);
+    let mut reconnect = Reconnect::new();
+    let mut ws = match connect("Market data", setup_ws, &mut reconnect, &mut shutdown, true).await {
+        Some(ws) => ws,
+        None if *shutdown.borrow() => return,
+        None => return notify_reconnect_gave_up("Market data", &reconnect),
+    };
+    // Kraken checksum of the top of each book last published
+    let mut checksums: HashMap<String, u32> = HashMap::new();
+    let mut ages = BookAges::new(Instant::now().into_std());
+    let mut sample = interval(Duration::from_millis(timers.book_sample_ms));
+    sample.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = sample.tick() => (),
+        }
+
+        let now = Instant::now().into_std();
+        let quiet = pairs.iter().all(|pair| ages.stale(pair, now, stale_after).is_some());
+        if ws.stream_closed() || quiet {
+            let reason = if quiet { "went quiet" } else { "was closed" };
+            for pair in pairs.iter() {
+                set_book_problem(pair, Some(BookProblem::Disconnected));
+            }
+            close_ws(ws).await;
+            let delay = match reconnect.next_delay(&config().reconnect) {
+                Some(delay) => delay,
+                None => return notify_reconnect_gave_up("Market data", &reconnect),
+            };
+            println!("[{} | RECONNECT] Market data stream {}, reconnecting in {:?}", time(), reason, delay);
+            notify_stream_close(reason, delay);
+            tokio::select! {
+                _ = shutdown.changed() => return,
+                _ = sleep(delay) => (),
+            }
+            ws = match connect("Market data", setup_ws, &mut reconnect, &mut shutdown, true).await {
+                Some(ws) => ws,
+                None if *shutdown.borrow() => return,
+                None => return notify_reconnect_gave_up("Market data", &reconnect),
+            };
+            let outage = reconnect.outage();
+            let count = reconnect.connected();
+            println!("[{} | RECONNECT] Market data reconnected after {:?}, reconnect #{}", time(), outage, count);
+            notify_successful_reconnect(outage, count);
+            checksums.clear();
+            ages = BookAges::new(Instant::now().into_std());
+            continue;
+        }
+
+        let changed: BTreeMap<String, BookData> = ws
+            .get_all_books()
+            .into_iter()
+            .filter(|(pair, book)| {
+                checksums.insert(pair.clone(), book.checksum()) != Some(book.checksum()) || book.checksum_failed
+            })
+            .collect();
+        for pair in changed.keys() {
+            ages.changed(pair, now);
+        }
+        for pair in pairs.iter() {
+            if let Some(stale) = ages.stale(pair, now, stale_after) {
+                set_book_problem(pair, Some(stale));
+            }
+        }
+        if changed.is_empty() {
+            continue;
+        }
+        // the simulated exchange is fed under the exchange lock
+        blocking(move || update_prices(changed)).await;
+        let _ = deltas.send(());
+    }
+    close_ws(ws).await;
+}
+
+/**
+ * Market data of a --mock run, synthetic or recorded books every second
+ * and no connection to Kraken
+ */
+async fn mock_market_data(deltas: watch::Sender<()>, mut shutdown: watch::Receiver<bool>) {
+    let mut feed = MockFeed::load();
+    let mut step = interval(Duration::from_secs(1));
+    step.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = step.tick() => (),
+        }
+        let books = feed.next_books();
+        blocking(move || update_prices(books)).await;
+        let _ = deltas.send(());
+    }
+}
+
+/**
+ * Follows our open orders on the private websocket and tells the order
+ * manager as soon as the pending order leaves the book. The order manager
+ * still polls, so the feed giving up only makes resolution slower.
+ */
+async fn order_feed(orders: mpsc::Sender<OrderCommand>, mut shutdown: watch::Receiver<bool>) {
+    let timers = config().timers;
+    let mut reconnect = Reconnect::new();
+    let mut sample = interval(Duration::from_millis(timers.book_sample_ms));
+    sample.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        let ws = match connect("Private feed", setup_private_ws, &mut reconnect, &mut shutdown, false).await {
+            Some(Some(ws)) => ws,
+            Some(None) => {
+                println!("[{} | PRIVATE FEED] Off, orders are polled", time());
+                let _ = shutdown.changed().await;
+                return;
+            },
+            None if *shutdown.borrow() => return,
+            None => {
+                send_telegram_message(markdown::escape(&format!(
+                    "⚠️ Private order feed could not reconnect within {}s, orders are polled from now on.",
+                    config().reconnect.window_secs
+                )));
+                let _ = shutdown.changed().await;
+                return;
+            },
+        };
+        if reconnect.attempts() > 0 {
+            let count = reconnect.connected();
+            println!("[{} | PRIVATE FEED] Reconnected, reconnect #{}", time(), count);
+        }
+        println!("[{} | PRIVATE FEED] Following open orders", time());
+
+        // the pending order, once the feed has seen it open
+        let mut seen: Option<TxId> = None;
+        let stopped = loop {
+            tokio::select! {
+                _ = shutdown.changed() => break true,
+                _ = sample.tick() => (),
+            }
+            if ws.stream_closed() {
+                break false;
+            }
+            let pending = match pending_txid() {
+                Some(txid) if is_waiting_order_resolution() => txid,
+                _ => {
+                    seen = None;
+                    continue;
+                },
+            };
+            if ws.get_open_orders().contains_key(&pending) {
+                seen = Some(pending);
+            } else if seen.as_ref() == Some(&pending) {
+                seen = None;
+                if orders.send(OrderCommand::OrderClosed(pending)).await.is_err() {
+                    break true;
+                }
+            }
+        };
+        close_ws(ws).await;
+        if stopped {
+            return;
+        }
+        let delay = match reconnect.next_delay(&config().reconnect) {
+            Some(delay) => delay,
+            None => continue,
+        };
+        println!("[{} | PRIVATE FEED] Stream closed, reconnecting in {:?}", time(), delay);
+        tokio::select! {
+            _ = shutdown.changed() => return,
+            _ = sleep(delay) => (),
+        }
+    }
+}
+
+/**
+ * Evaluates the hop on book changes and asks the order manager to place it.
+ * Changes within the debounce window are evaluated once, hop decisions are at
+ * least min_decision_interval_ms apart. Stays idle while an order is pending
+ * or the balance is unknown.
+ */
+async fn run_strategy(
+    mut deltas: watch::Receiver<()>,
+    balances: watch::Receiver<Option<Balance>>,
+    orders: mpsc::Sender<OrderCommand>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let timers = config().timers;
+    let min_decision_interval = Duration::from_millis(timers.min_decision_interval_ms);
+    let mut last_decision: Option<Instant> = None;
+    let mut last_proposal: Option<Instant> = None;
+    // why the strategy is holding back, logged when it changes
+    let mut holding: Option<String> = None;
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            changed = deltas.changed() => if changed.is_err() { break },
+        }
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = sleep(Duration::from_millis(timers.debounce_ms)) => (),
+        }
+        deltas.borrow_and_update();
+
+        if is_waiting_order_resolution() {
+            continue;
+        }
+
+        if let Some(problems) = book_problems() {
+            log_holding(&mut holding, Some(format!("Trading suspended: {}", problems)));
+            continue;
+        }
+
+        /*
+        * first run: measure gains from the current price
+        */
+        if !blocking(ensure_reference_price).await {
+            continue;
+        }
+
+        let balance = match balances.borrow().clone() {
+            Some(balance) => balance,
+            None => continue,
+        };
+
+        /*
+        * calc my position from balance
+        */
+        let position = get_my_position(&balance);
+
+        /*
+        * get current gain
+        */
+        let (gain, net_gain) = match (calculate_gain(&position), calculate_net_gain(&position)) {
+            (Some(gain), Some(net_gain)) => (gain, net_gain),
+            _ => {
+                log_holding(&mut holding, Some(format!("No gain for position {:?} yet", position)));
+                continue;
+            },
+        };
+
+        /*
+        * hop strat eval
+        */
+        if !strategy::should_hop(&position, net_gain, &config().strategy) {
+            log_holding(&mut holding, None);
+            continue;
+        }
+        if trading_paused() {
+            log_holding(&mut holding, Some("Trading paused, not hopping".to_string()));
+            continue;
+        }
+        if last_decision.is_some_and(|at| at.elapsed() < min_decision_interval) {
+            log_holding(&mut holding, Some(format!("Hop decided less than {:?} ago, waiting", min_decision_interval)));
+            continue;
+        }
+        log_holding(&mut holding, None);
+        last_decision = Some(Instant::now());
+
+        /*
+        * approval mode: propose instead of hopping, one proposal at a time
+        */
+        if config().strategy.approval {
+            let approval_timeout = Duration::from_secs(config().telegram.approval_timeout_secs);
+            if proposal::is_open(Kind::Approval) || last_proposal.is_some_and(|at| at.elapsed() < approval_timeout) {
+                continue;
+            }
+            last_proposal = Some(Instant::now());
+            telegram::send_proposal(Proposal { kind: Kind::Approval, position, gain, net_gain, order: None });
+            continue;
+        }
+        let reason = threshold_reason(&position, net_gain);
+        let request = HopRequest { position, gain, net_gain, reason, recheck: true, order: None, reply: None };
+        if orders.send(OrderCommand::Hop(request)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/**
+ * The strategy runs on every book change, so why it holds back
+ * is only logged when that changes
+ */
+fn log_holding(holding: &mut Option<String>, reason: Option<String>) {
+    if *holding == reason {
+        return;
+    }
+    if let Some(reason) = &reason {
+        println!("[{} | STRATEGY] {}", time(), reason);
+    }
+    *holding = reason;
+}
+
+/**
+ * Places hops, follows the pending order until it resolves and keeps the
+ * balance the strategy works with up to date
+ */
+struct OrderManager {
+    balance: Option<Balance>,
+    balances: watch::Sender<Option<Balance>>,
+    order_abort_count: u64,
+    // hops are refused until then after a failed placement
+    hop_backoff_until: Instant,
+    balance_retry: Retry,
+    order_check_retry: Retry,
+    hop_retry: Retry,
+}
+
+async fn order_manager(
+    mut commands: mpsc::Receiver<OrderCommand>,
+    balances: watch::Sender<Option<Balance>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut manager = OrderManager {
+        balance: None,
+        balances,
+        order_abort_count: 0,
+        hop_backoff_until: Instant::now(),
+        balance_retry: Retry::new("Balance fetch"),
+        order_check_retry: Retry::new("Order status check"),
+        hop_retry: Retry::new("Order placement"),
+    };
+    let next_check = sleep(Duration::ZERO);
+    tokio::pin!(next_check);
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            command = commands.recv() => match command {
+                Some(command) => {
+                    if let Some(wait) = manager.handle(command).await {
+                        next_check.as_mut().reset(Instant::now() + wait);
+                    }
+                },
+                None => break,
+            },
+            _ = &mut next_check => {
+                let wait = manager.check().await;
+                next_check.as_mut().reset(Instant::now() + wait);
+            },
+        }
+    }
+}
+
+impl OrderManager {
+    /**
+     * Checks on the pending order or refreshes the balance,
+     * returns when to check again
+     */
+    async fn check(&mut self) -> Duration {
+        let timers = config().timers;
+        /*
+        *  check order resolution update
+        *  NOTE: must be above balance update, so it
+        *  updates after order resolution
+        */
+        if is_waiting_order_resolution() {
+            let mut wait = timers.order_poll_interval_ms;
+            match blocking(check_last_order).await {
+                Ok(Some(order)) => {
+                    handle_success(&mut self.order_check_retry);
+                    blocking(move || resolve_last_order(&order)).await;
+                    self.order_abort_count = 0;
+                    return Duration::from_millis(timers.poll_interval_ms);
+                },
+                Ok(None) => handle_success(&mut self.order_check_retry),
+                Err(e) => wait = wait.max(handle_failure(&mut self.order_check_retry, &e)),
+            }
+            println!("[{} | ORDER RESOLUTION WAIT] Waiting for order to resolve", time());
+            if self.order_abort_count > timers.order_abort_timeout {
+                // a re-placed remainder starts a fresh timeout
+                if blocking(abort_order).await {
+                    self.order_abort_count = 0;
+                    return Duration::from_millis(timers.poll_interval_ms);
+                }
+            } else {
+                self.order_abort_count += 1;
+            }
+            return Duration::from_millis(wait);
+        }
+
+        /*
+        * refresh my balance, deposits and withdrawals change it too.
+        * A failed refresh keeps the last known balance
+        */
+        match blocking(get_account_balance).await {
+            Ok(balance) => {
+                handle_success(&mut self.balance_retry);
+                if self.balance.as_ref() != Some(&balance) {
+                    self.balance = Some(balance.clone());
+                    self.balances.send_replace(Some(balance));
+                }
+            },
+            Err(e) => return Duration::from_millis(handle_failure(&mut self.balance_retry, &e)),
+        }
+        Duration::from_millis(timers.poll_interval_ms)
+    }
+
+    /**
+     * Returns when to check next if the command changed the pending order
+     */
+    async fn handle(&mut self, command: OrderCommand) -> Option<Duration> {
+        match command {
+            OrderCommand::Hop(request) => self.hop(request).await,
+            OrderCommand::Abort(reply) => {
+                let _ = reply.send(blocking(abort_order_command).await);
+                self.order_abort_count = 0;
+                self.mark_balance_stale();
+                Some(Duration::ZERO)
+            },
+            OrderCommand::OrderClosed(txid) => {
+                if pending_txid().as_deref() != Some(txid.as_str()) {
+                    return None;
+                }
+                println!("[{} | PRIVATE FEED] Order {} left the book", time(), txid);
+                Some(Duration::ZERO)
+            },
+        }
+    }
+
+    /**
+     * Hops decided on a balance or order that changed since are dropped
+     */
+    async fn hop(&mut self, request: HopRequest) -> Option<Duration> {
+        let HopRequest { position, gain, net_gain, reason, recheck, order, reply } = request;
+        let answer = |reply: Option<oneshot::Sender<String>>, text: String| {
+            if let Some(reply) = reply {
+                let _ = reply.send(markdown::escape(&text));
+            }
+        };
+        let refuse = |reply: Option<oneshot::Sender<String>>, refusal: &str| {
+            println!("[{} | ORDER MANAGER] Not hopping ({}): {}", time(), reason, refusal);
+            let journaled = format!("{}, refused: {}", reason, refusal);
+            let journal = blocking(move || journal_hop(Event::Refused, &position, gain, net_gain, journaled));
+            answer(reply, format!("Not hopping: {}", refusal));
+            journal
+        };
+        let refused = if is_waiting_order_resolution() {
+            Some("An order is still pending.")
+        } else if Instant::now() < self.hop_backoff_until {
+            Some("Order placement is backing off after a failure.")
+        } else if book_problems().is_some() {
+            Some("Trading is suspended on bad book data.")
+        } else if trading_paused() {
+            Some("Trading is paused.")
+        } else {
+            None
+        };
+        if let Some(refusal) = refused {
+            refuse(reply, refusal).await;
+            return None;
+        }
+        let balance = match self.balance.clone() {
+            Some(balance) if get_my_position(&balance) == position => balance,
+            Some(_) => {
+                refuse(reply, "the position changed since.").await;
+                return None;
+            },
+            None => {
+                refuse(reply, "the balance is being refreshed, try again.").await;
+                return None;
+            },
+        };
+        let (gain, net_gain) = if recheck {
+            match blocking(move || (calculate_gain(&position), calculate_net_gain(&position))).await {
+                (Some(now), Some(net_now)) if strategy::should_hop(&position, net_now, &config().strategy) => (now, net_now),
+                (_, net_now) => {
+                    let refusal = format!(
+                        "the net gain is {} now, below the threshold.",
+                        net_now.map_or("unknown".to_string(), |net_now| net_now.round_dp(6).to_string()),
+                    );
+                    refuse(reply, &refusal).await;
+                    return None;
+                },
+            }
+        } else {
+            (gain, net_gain)
+        };
+
+        if let Some((_, price, _)) = order {
+            let priced = balance.clone();
+            let now = blocking(move || hop_order(&position, &priced).map(|(_, now, _)| now)).await;
+            let refusal = match now {
+                Ok(now) if (now - price).abs() <= pair_info().tick_size => None,
+                Ok(now) => Some(format!("the price moved from {} to {} since the preview.", price, now)),
+                Err(e) => Some(format!("could not price the order: {}", e)),
+            };
+            if let Some(refusal) = refusal {
+                refuse(reply, &refusal).await;
+                return None;
+            }
+        }
+
+        /*
+        * execute hop
+        */
+        let placed = blocking(move || {
+            journal_hop(Event::Decision, &position, gain, net_gain, reason);
+            let placed = order
+                .map_or_else(|| hop_order(&position, &balance), Ok)
+                .and_then(|(side, price, volume)| execute_hop(side, price, volume));
+            journal_placement(&placed, Some(gain), Some(net_gain), "hop");
+            placed
+        }).await;
+        // the balance changed or may be what made the order fail
+        self.mark_balance_stale();
+        match placed {
+            Ok((order_response, order)) => {
+                handle_success(&mut self.hop_retry);
+                println!("[{} | EXECUTED TRADE] Order placed: {:#?}", time(), order_response);
+                answer(reply, format!("Order placed: {}", order_response.descr.order));
+                let price = order.price;
+                blocking(move || {
+                    update_last_order(order);
+                    telegram::notify_order_placed_telegram(&order_response, &price, &gain, &net_gain, &position);
+                }).await;
+                Some(Duration::from_millis(config().timers.poll_interval_ms))
+            },
+            Err(e) => {
+                answer(reply, format!("Could not place the order: {}", e));
+                let backoff = Duration::from_millis(handle_failure(&mut self.hop_retry, &e));
+                self.hop_backoff_until = Instant::now() + backoff;
+                Some(backoff)
+            },
+        }
+    }
+
+    fn mark_balance_stale(&mut self) {
+        self.balance = None;
+        self.balances.send_replace(None);
+    }
+}
+
+/**
+ * Logs a failed attempt, alerts on Telegram when the retry policy
+ * says so and returns how long to back off in millis
+ */
+fn handle_failure(retry: &mut Retry, error: &BotError) -> u64 {
+    let failure = retry.failed(error, &config().errors);
+    println!("[{} | RETRY] {}, backing off {:?}", time(), error, failure.backoff);
+    if let Some(alert) = failure.alert {
+        send_telegram_message(markdown::escape(&alert));
+    }
+    failure.backoff.as_millis() as u64
+}
+
+fn handle_success(retry: &mut Retry) {
+    if let Some(message) = retry.succeeded() {
+        send_telegram_message(markdown::escape(&message));
+    }
+}
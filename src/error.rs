@@ -0,0 +1,112 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::config::ErrorsConfig;
+
+/**
+ * What can go wrong while trading. Exchange errors are mostly transient,
+ * a rejected order will be rejected again until something changes.
+ */
+#[derive(Debug)]
+pub enum BotError {
+    Exchange(Box<krakenrs::Error>),
+    // refused locally or by the exchange, e.g. below the minimum or insufficient funds
+    Rejected(String),
+    // no price or balance to work with yet
+    MissingData(String),
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BotError::Exchange(e) => write!(f, "exchange error: {}", e),
+            BotError::Rejected(reason) => write!(f, "order rejected: {}", reason),
+            BotError::MissingData(what) => write!(f, "missing data: {}", what),
+        }
+    }
+}
+
+impl From<krakenrs::Error> for BotError {
+    /**
+     * Kraken answers order problems with EOrder errors and bad
+     * arguments with EGeneral:Invalid arguments
+     */
+    fn from(e: krakenrs::Error) -> Self {
+        match &e {
+            krakenrs::Error::KrakenErrors(errors) if errors.iter().any(|error| {
+                error.starts_with("EOrder:") || error.starts_with("EGeneral:Invalid")
+            }) => BotError::Rejected(errors.join(", ")),
+            _ => BotError::Exchange(Box::new(e)),
+        }
+    }
+}
+
+impl BotError {
+    pub fn is_transient(&self) -> bool {
+        !matches!(self, BotError::Rejected(_))
+    }
+}
+
+/**
+ * Consecutive failures of one operation. Backs off exponentially and
+ * alerts once per streak, right away for rejections, after
+ * `alert_after` failures for transient errors.
+ */
+pub struct Retry {
+    name: &'static str,
+    failures: u32,
+    alerted: bool,
+}
+
+pub struct Failure {
+    pub backoff: Duration,
+    // Telegram message, set once per streak
+    pub alert: Option<String>,
+}
+
+impl Retry {
+    pub fn new(name: &'static str) -> Self {
+        Retry {
+            name,
+            failures: 0,
+            alerted: false,
+        }
+    }
+
+    pub fn failed(&mut self, error: &BotError, policy: &ErrorsConfig) -> Failure {
+        self.failures += 1;
+        let backoff_ms = if error.is_transient() {
+            let exponent = (self.failures - 1).min(31);
+            policy.retry_backoff_ms.saturating_mul(1 << exponent).min(policy.retry_backoff_max_ms)
+        } else {
+            policy.retry_backoff_max_ms
+        };
+        let backoff = Duration::from_millis(backoff_ms);
+
+        let due = !error.is_transient() || self.failures >= policy.alert_after;
+        let alert = if due && !self.alerted {
+            self.alerted = true;
+            Some(format!(
+                "⚠️ {} failed {} time(s): {}. Retrying in {}s.",
+                self.name, self.failures, error, backoff.as_secs()
+            ))
+        } else {
+            None
+        };
+        Failure { backoff, alert }
+    }
+
+    /**
+     * Resets the streak, returns a recovery message if it was alerted on
+     */
+    pub fn succeeded(&mut self) -> Option<String> {
+        let message = if self.alerted {
+            Some(format!("✅ {} recovered after {} failure(s).", self.name, self.failures))
+        } else {
+            None
+        };
+        self.failures = 0;
+        self.alerted = false;
+        message
+    }
+}
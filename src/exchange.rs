@@ -0,0 +1,725 @@
+use krakenrs::{
+    AddOrderResponse, BalanceResponse, BsType, CancelOrderResponse, Error,
+    FeeTierInfo, KrakenRestAPI, KrakenRestClient, KrakenRestConfig, LimitOrder, MarketOrder, OrderAdded, OrderInfo,
+    OrderStatus, TxId,
+};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::config::config;
+use crate::error::BotError;
+
+/**
+ * Everything the bot needs from an exchange. Kraken is the real
+ * implementation, the simulated exchange keeps balances in memory
+ * so the hop loop can run without touching real funds.
+ */
+// krakenrs::Error is large, it is boxed once it becomes a BotError
+#[allow(clippy::result_large_err)]
+pub trait Exchange: Send {
+    fn get_account_balance(&self) -> Result<BalanceResponse, Error>;
+    fn add_limit_order(&mut self, order: LimitOrder) -> Result<AddOrderResponse, Error>;
+    fn add_market_order(&mut self, order: MarketOrder) -> Result<AddOrderResponse, Error>;
+    fn get_open_orders(&self) -> Result<HashMap<TxId, OrderState>, Error>;
+    fn cancel_order(&mut self, txid: &str) -> Result<CancelOrderResponse, Error>;
+    fn query_order(&self, txid: &str) -> Result<OrderState, Error>;
+    fn get_fees(&self, pair: &str) -> Result<Fees, Error>;
+    // `pair` is the websocket name, e.g. ETH/XBT
+    fn get_pair_info(&self, pair: &str) -> Result<PairInfo, Error>;
+
+    /**
+     * Token for the private websocket feed. Simulated exchanges have
+     * no feed, their orders are only polled.
+     */
+    fn get_websockets_token(&self) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    /**
+     * Best bid and ask seen on the market feed. Only simulated
+     * exchanges care about this, Kraken has its own books.
+     */
+    fn on_book(&mut self, _pair: &str, _best_bid: Decimal, _best_ask: Decimal) {}
+}
+
+/**
+ * Exchange agnostic view of an order
+ */
+#[derive(Debug, Clone)]
+pub struct OrderState {
+    pub txid: TxId,
+    pub status: OrderStatus,
+    pub volume: Decimal,
+    pub volume_executed: Decimal,
+    pub avg_price: Decimal,
+    pub fee: Decimal,
+}
+
+impl OrderState {
+    fn from_info(txid: &str, info: &OrderInfo) -> Self {
+        OrderState {
+            txid: txid.to_string(),
+            status: info.status.clone(),
+            volume: info.vol,
+            volume_executed: info.vol_exec,
+            avg_price: info.price,
+            fee: info.fee,
+        }
+    }
+}
+
+/**
+ * Fee tier of the account as fractions of the order value
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fees {
+    pub maker: Decimal,
+    pub taker: Decimal,
+}
+
+impl Fees {
+    pub fn from_config() -> Self {
+        let fees = config().fees;
+        Fees {
+            maker: Decimal::from_f64(fees.maker).unwrap_or(Decimal::ZERO),
+            taker: Decimal::from_f64(fees.taker).unwrap_or(Decimal::ZERO),
+        }
+    }
+}
+
+/**
+ * Precision and minimum order size of a pair
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairInfo {
+    pub price_decimals: u32,
+    pub lot_decimals: u32,
+    // in the base asset
+    pub ordermin: Decimal,
+    pub tick_size: Decimal,
+}
+
+impl PairInfo {
+    pub fn from_config() -> Self {
+        let market = config().market;
+        PairInfo {
+            price_decimals: market.price_decimals,
+            lot_decimals: market.lot_decimals,
+            ordermin: Decimal::from_f64(market.ordermin).unwrap_or(Decimal::ZERO),
+            tick_size: Decimal::new(1, market.price_decimals),
+        }
+    }
+
+    /**
+     * Nearest valid price
+     */
+    pub fn round_price(&self, price: Decimal) -> Decimal {
+        let ticks = (price / self.tick_size).round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero);
+        (ticks * self.tick_size).round_dp(self.price_decimals).normalize()
+    }
+
+    /**
+     * Rounded down so an order never asks for more than we hold
+     */
+    pub fn round_volume(&self, volume: Decimal) -> Decimal {
+        volume.round_dp_with_strategy(self.lot_decimals, RoundingStrategy::ToZero).normalize()
+    }
+
+    /**
+     * Rounded price and volume, refused when Kraken would reject the volume as too small
+     */
+    pub fn round_order(&self, price: Decimal, volume: Decimal) -> Result<(Decimal, Decimal), BotError> {
+        let (price, volume) = (self.round_price(price), self.round_volume(volume));
+        if volume.is_zero() || volume < self.ordermin {
+            return Err(BotError::Rejected(format!("volume {} is below the minimum of {}", volume, self.ordermin)));
+        }
+        Ok((price, volume))
+    }
+
+    /**
+     * Buy spending at most `quote` with the fee. The volume is what the
+     * rounded price pays for, a price rounded up would otherwise overspend.
+     */
+    pub fn round_buy(&self, price: Decimal, quote: Decimal, fee: Decimal) -> Result<(Decimal, Decimal), BotError> {
+        let price = self.round_price(price);
+        let volume = quote.checked_div(price * (Decimal::ONE + fee)).unwrap_or(Decimal::ZERO);
+        self.round_order(price, volume)
+    }
+}
+
+/*
+ * Kraken
+ */
+
+pub struct KrakenExchange {
+    api: KrakenRestAPI,
+    // krakenrs has no QueryOrders call, so we issue it through the raw client
+    client: KrakenRestClient,
+}
+
+#[derive(Serialize)]
+struct QueryOrdersRequest {
+    txid: String,
+}
+
+// krakenrs' AssetPair lacks the tick size
+#[derive(Serialize)]
+struct AssetPairsRequest {}
+
+#[derive(Deserialize)]
+struct AssetPairInfo {
+    wsname: Option<String>,
+    pair_decimals: u32,
+    lot_decimals: u32,
+    ordermin: Option<Decimal>,
+    tick_size: Option<Decimal>,
+}
+
+#[derive(Deserialize)]
+struct KrakenResponse<T> {
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+impl KrakenExchange {
+    #[allow(clippy::result_large_err)]
+    pub fn new(config: KrakenRestConfig) -> Result<Self, Error> {
+        Ok(KrakenExchange {
+            api: KrakenRestAPI::try_from(config.clone())?,
+            client: KrakenRestClient::try_from(config)?,
+        })
+    }
+}
+
+impl Exchange for KrakenExchange {
+    fn get_account_balance(&self) -> Result<BalanceResponse, Error> {
+        self.api.get_account_balance()
+    }
+
+    fn add_limit_order(&mut self, order: LimitOrder) -> Result<AddOrderResponse, Error> {
+        self.api.add_limit_order(order, None, false)
+    }
+
+    fn add_market_order(&mut self, order: MarketOrder) -> Result<AddOrderResponse, Error> {
+        self.api.add_market_order(order, None, false)
+    }
+
+    fn get_open_orders(&self) -> Result<HashMap<TxId, OrderState>, Error> {
+        let response = self.api.get_open_orders(None)?;
+        Ok(response.open
+            .iter()
+            .map(|(txid, info)| (txid.clone(), OrderState::from_info(txid, info)))
+            .collect())
+    }
+
+    fn cancel_order(&mut self, txid: &str) -> Result<CancelOrderResponse, Error> {
+        self.api.cancel_order(txid.to_string())
+    }
+
+    fn query_order(&self, txid: &str) -> Result<OrderState, Error> {
+        let response: KrakenResponse<HashMap<TxId, OrderInfo>> = self.client.query_private(
+            "QueryOrders",
+            QueryOrdersRequest { txid: txid.to_string() },
+        )?;
+        if !response.error.is_empty() {
+            return Err(Error::KrakenErrors(response.error));
+        }
+        match response.result.and_then(|orders| orders.get(txid).map(|info| OrderState::from_info(txid, info))) {
+            Some(order) => Ok(order),
+            None => Err(Error::MissingResultJson),
+        }
+    }
+
+    /**
+     * Kraken reports fees in percent, keyed by its own name for the pair
+     */
+    fn get_fees(&self, pair: &str) -> Result<Fees, Error> {
+        let response = self.api.get_trade_volume(vec![pair.to_string()])?;
+        let percent = |tiers: &HashMap<String, FeeTierInfo>| tiers.values().next().map(|tier| tier.fee);
+        let taker = percent(&response.fees).ok_or(Error::MissingResultJson)?;
+        // pairs without a maker schedule charge the taker fee
+        let maker = percent(&response.fees_maker).unwrap_or(taker);
+        Ok(Fees {
+            maker: maker / Decimal::ONE_HUNDRED,
+            taker: taker / Decimal::ONE_HUNDRED,
+        })
+    }
+
+    fn get_pair_info(&self, pair: &str) -> Result<PairInfo, Error> {
+        let response: KrakenResponse<HashMap<String, AssetPairInfo>> = self.client.query_public("AssetPairs", AssetPairsRequest {})?;
+        if !response.error.is_empty() {
+            return Err(Error::KrakenErrors(response.error));
+        }
+        let info = response.result
+            .and_then(|pairs| pairs.into_values().find(|info| info.wsname.as_deref() == Some(pair)))
+            .ok_or_else(|| Error::KrakenErrors(vec![format!("EQuery:Unknown asset pair {}", pair)]))?;
+        Ok(PairInfo {
+            price_decimals: info.pair_decimals,
+            lot_decimals: info.lot_decimals,
+            ordermin: info.ordermin.unwrap_or(Decimal::ZERO),
+            tick_size: info.tick_size.unwrap_or_else(|| Decimal::new(1, info.pair_decimals)),
+        })
+    }
+
+    fn get_websockets_token(&self) -> Result<Option<String>, Error> {
+        Ok(Some(self.api.get_websockets_token()?.token))
+    }
+}
+
+/*
+ * Simulated
+ */
+
+struct SimulatedOrder {
+    bs_type: BsType,
+    base: String,
+    quote: String,
+    price: Decimal,
+    state: OrderState,
+}
+
+pub struct SimulatedExchange {
+    balances: HashMap<String, Decimal>,
+    orders: HashMap<TxId, SimulatedOrder>,
+    next_txid: u64,
+    // (base, quote) -> (best bid, best ask)
+    books: HashMap<(String, String), (Decimal, Decimal)>,
+    // fill only when the live book crosses the limit price
+    fill_on_cross: bool,
+    // where to keep balances between runs
+    balance_file: Option<String>,
+}
+
+impl SimulatedExchange {
+    /**
+     * Orders fill immediately at their limit price
+     */
+    pub fn new(balances: HashMap<String, Decimal>) -> Self {
+        SimulatedExchange {
+            balances,
+            orders: HashMap::new(),
+            next_txid: 1,
+            books: HashMap::new(),
+            fill_on_cross: false,
+            balance_file: None,
+        }
+    }
+
+    /**
+     * Paper trading: orders rest until the live best bid/ask crosses
+     * their limit price and balances are persisted after every fill
+     */
+    pub fn paper(balances: HashMap<String, Decimal>, balance_file: &str) -> Self {
+        SimulatedExchange {
+            fill_on_cross: true,
+            balance_file: Some(balance_file.to_string()),
+            ..SimulatedExchange::new(balances)
+        }
+    }
+
+    /**
+     * Price at which the order would execute against the current book
+     */
+    fn crossing_price(&self, order: &SimulatedOrder) -> Option<Decimal> {
+        let (best_bid, best_ask) = self.books.get(&(order.base.clone(), order.quote.clone()))?;
+        match order.bs_type {
+            BsType::Buy if *best_ask <= order.price => Some(order.price.min(*best_ask)),
+            BsType::Sell if *best_bid >= order.price => Some(order.price.max(*best_bid)),
+            _ => None,
+        }
+    }
+
+    fn save_balances(&self) {
+        if let Some(file) = &self.balance_file {
+            let saved = serde_json::to_string_pretty(&self.balances)
+                .map_err(|e| e.to_string())
+                .and_then(|content| std::fs::write(file, content).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                println!("[SIMULATED EXCHANGE] Error saving {}: {:#?}", file, e);
+            }
+        }
+    }
+
+    /**
+     * Places a limit order, `market` ones cross the book. Mock orders fill
+     * at once at their limit price as if they had rested there, paper orders
+     * fill now if they cross the book and rest otherwise.
+     */
+    #[allow(clippy::result_large_err)]
+    fn place(&mut self, order: LimitOrder, market: bool) -> Result<AddOrderResponse, Error> {
+        let (base, quote) = match pair_keys(&order.pair) {
+            Some(keys) => keys,
+            None => return Err(rejected("EQuery:Unknown asset pair")),
+        };
+        let volume = Decimal::from_str(&order.volume).map_err(|_| rejected("EGeneral:Invalid arguments:volume"))?;
+        let price = Decimal::from_str(&order.price).map_err(|_| rejected("EGeneral:Invalid arguments:price"))?;
+
+        let txid = format!("SIM-{:06}", self.next_txid);
+        let description = format!("{} {} {} @ limit {}", order.bs_type, order.volume, order.pair, order.price).to_lowercase();
+        let simulated = SimulatedOrder {
+            bs_type: order.bs_type,
+            base,
+            quote,
+            price,
+            state: OrderState {
+                txid: txid.clone(),
+                status: OrderStatus::Open,
+                volume,
+                volume_executed: Decimal::ZERO,
+                avg_price: Decimal::ZERO,
+                fee: Decimal::ZERO,
+            },
+        };
+        let (fill_price, maker) = if self.fill_on_cross {
+            let crossing = self.crossing_price(&simulated);
+            (crossing, crossing.is_none())
+        } else {
+            (Some(price), !market)
+        };
+
+        // like Kraken, the fee is paid in the quote asset on top of a buy
+        let (pays, needed) = match simulated.bs_type {
+            BsType::Buy => (&simulated.quote, volume * price * (Decimal::ONE + fee_rate(maker))),
+            BsType::Sell => (&simulated.base, volume),
+        };
+        if self.balances.get(pays).copied().unwrap_or(Decimal::ZERO) < needed {
+            return Err(rejected("EOrder:Insufficient funds"));
+        }
+
+        self.next_txid += 1;
+        self.orders.insert(txid.clone(), simulated);
+        if let Some(fill_price) = fill_price {
+            self.fill(&txid, fill_price, maker);
+        }
+
+        Ok(AddOrderResponse {
+            descr: OrderAdded { order: description, close: String::new() },
+            txid: vec![txid],
+        })
+    }
+
+    /**
+     * Fills the whole order. A resting order pays the maker fee, one that
+     * crossed the book when placed the taker fee, in the quote asset like
+     * Kraken: on top of the cost of a buy, out of the proceeds of a sell.
+     */
+    fn fill(&mut self, txid: &str, price: Decimal, maker: bool) {
+        let order = match self.orders.get_mut(txid) {
+            Some(order) => order,
+            None => return,
+        };
+        if order.state.status != OrderStatus::Open {
+            return;
+        }
+        let volume = order.state.volume;
+        let cost = volume * price;
+        let fee = cost * fee_rate(maker);
+        let (gets, gets_amount, pays, pays_amount) = match order.bs_type {
+            BsType::Buy => (&order.base, volume, &order.quote, cost + fee),
+            BsType::Sell => (&order.quote, cost - fee, &order.base, volume),
+        };
+        *self.balances.entry(gets.clone()).or_insert(Decimal::ZERO) += gets_amount;
+        *self.balances.entry(pays.clone()).or_insert(Decimal::ZERO) -= pays_amount;
+        order.state.status = OrderStatus::Closed;
+        order.state.volume_executed = volume;
+        order.state.avg_price = price;
+        order.state.fee = fee;
+        self.save_balances();
+    }
+}
+
+fn fee_rate(maker: bool) -> Decimal {
+    let fees = Fees::from_config();
+    if maker { fees.maker } else { fees.taker }
+}
+
+fn pair_keys(pair: &str) -> Option<(String, String)> {
+    pair.split_once('/').map(|(base, quote)| (balance_key(base), balance_key(quote)))
+}
+
+/**
+ * Maps the short asset codes used in pair names to the keys Kraken
+ * uses in the balance response
+ */
+fn balance_key(asset: &str) -> String {
+    let market = config().market;
+    if asset == market.base {
+        return market.base_balance_key;
+    }
+    if asset == market.quote {
+        return market.quote_balance_key;
+    }
+    match asset {
+        "XBT" | "BTC" => "XXBT".to_string(),
+        "ETH" => "XETH".to_string(),
+        "EUR" => "ZEUR".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn rejected(reason: &str) -> Error {
+    Error::KrakenErrors(vec![reason.to_string()])
+}
+
+impl Exchange for SimulatedExchange {
+    fn get_account_balance(&self) -> Result<BalanceResponse, Error> {
+        Ok(self.balances.clone())
+    }
+
+    fn add_limit_order(&mut self, order: LimitOrder) -> Result<AddOrderResponse, Error> {
+        self.place(order, false)
+    }
+
+    /**
+     * Placed as a limit order at the best price of the other side of the book
+     */
+    fn add_market_order(&mut self, order: MarketOrder) -> Result<AddOrderResponse, Error> {
+        let keys = pair_keys(&order.pair).ok_or_else(|| rejected("EQuery:Unknown asset pair"))?;
+        let price = match (self.books.get(&keys), &order.bs_type) {
+            (Some((_, best_ask)), BsType::Buy) => *best_ask,
+            (Some((best_bid, _)), BsType::Sell) => *best_bid,
+            (None, _) => return Err(rejected("EOrder:No order book seen for pair")),
+        };
+        let description = format!("{} {} {} @ market", order.bs_type, order.volume, order.pair).to_lowercase();
+        let mut response = self.place(LimitOrder {
+            bs_type: order.bs_type,
+            volume: order.volume,
+            pair: order.pair,
+            price: price.to_string(),
+            oflags: order.oflags,
+        }, true)?;
+        response.descr.order = description;
+        Ok(response)
+    }
+
+    fn get_open_orders(&self) -> Result<HashMap<TxId, OrderState>, Error> {
+        Ok(self.orders
+            .iter()
+            .filter(|(_, order)| order.state.status == OrderStatus::Open)
+            .map(|(txid, order)| (txid.clone(), order.state.clone()))
+            .collect())
+    }
+
+    fn cancel_order(&mut self, txid: &str) -> Result<CancelOrderResponse, Error> {
+        match self.orders.get_mut(txid) {
+            Some(order) if order.state.status == OrderStatus::Open => {
+                order.state.status = OrderStatus::Canceled;
+                Ok(CancelOrderResponse { count: 1, pending: false })
+            },
+            Some(_) => Ok(CancelOrderResponse { count: 0, pending: false }),
+            None => Err(rejected("EOrder:Unknown order")),
+        }
+    }
+
+    fn query_order(&self, txid: &str) -> Result<OrderState, Error> {
+        match self.orders.get(txid) {
+            Some(order) => Ok(order.state.clone()),
+            None => Err(rejected("EOrder:Unknown order")),
+        }
+    }
+
+    fn get_fees(&self, _pair: &str) -> Result<Fees, Error> {
+        Ok(Fees::from_config())
+    }
+
+    fn get_pair_info(&self, _pair: &str) -> Result<PairInfo, Error> {
+        Ok(PairInfo::from_config())
+    }
+
+    fn on_book(&mut self, pair: &str, best_bid: Decimal, best_ask: Decimal) {
+        let keys = match pair_keys(pair) {
+            Some(keys) => keys,
+            None => return,
+        };
+        self.books.insert(keys, (best_bid, best_ask));
+        if !self.fill_on_cross {
+            return;
+        }
+
+        let fills: Vec<(TxId, Decimal)> = self.orders
+            .iter()
+            .filter(|(_, order)| order.state.status == OrderStatus::Open)
+            .filter_map(|(txid, order)| self.crossing_price(order).map(|price| (txid.clone(), price)))
+            .collect();
+        for (txid, price) in fills {
+            self.fill(&txid, price, true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    // ETH/XBT on Kraken
+    fn eth_xbt() -> PairInfo {
+        PairInfo {
+            price_decimals: 5,
+            lot_decimals: 8,
+            ordermin: dec!(0.01),
+            tick_size: dec!(0.00001),
+        }
+    }
+
+    #[test]
+    fn prices_round_to_the_nearest_tick() {
+        let info = eth_xbt();
+        assert_eq!(info.round_price(dec!(0.0512345)), dec!(0.05123));
+        assert_eq!(info.round_price(dec!(0.0512351)), dec!(0.05124));
+        assert_eq!(info.round_price(dec!(0.051235)), dec!(0.05124));
+        assert_eq!(info.round_price(dec!(0.05)), dec!(0.05));
+    }
+
+    #[test]
+    fn prices_round_to_coarser_ticks() {
+        let info = PairInfo { price_decimals: 2, tick_size: dec!(0.05), ..eth_xbt() };
+        assert_eq!(info.round_price(dec!(3000.12)), dec!(3000.1));
+        assert_eq!(info.round_price(dec!(3000.13)), dec!(3000.15));
+    }
+
+    #[test]
+    fn volumes_round_toward_zero() {
+        let info = eth_xbt();
+        assert_eq!(info.round_volume(dec!(1.234567899)), dec!(1.23456789));
+        assert_eq!(info.round_volume(dec!(0.000000009)), Decimal::ZERO);
+        assert_eq!(info.round_volume(dec!(2)), dec!(2));
+    }
+
+    #[test]
+    fn orders_below_ordermin_are_refused() {
+        let info = eth_xbt();
+        assert_eq!(info.round_order(dec!(0.0512345), dec!(0.5)).unwrap(), (dec!(0.05123), dec!(0.5)));
+        assert_eq!(info.round_order(dec!(0.05), dec!(0.01)).unwrap(), (dec!(0.05), dec!(0.01)));
+        assert!(matches!(info.round_order(dec!(0.05), dec!(0.009999999)), Err(BotError::Rejected(_))));
+        // rounding down can push a volume below the minimum
+        assert!(info.round_order(dec!(0.05), dec!(0.0099999999)).is_err());
+        let no_minimum = PairInfo { ordermin: Decimal::ZERO, ..eth_xbt() };
+        assert!(no_minimum.round_order(dec!(0.05), dec!(0.000000001)).is_err());
+    }
+
+    #[test]
+    fn buys_never_spend_more_than_the_quote() {
+        let info = eth_xbt();
+        let quote = dec!(0.01);
+        // the price rounds up to 0.05124
+        let (price, volume) = info.round_buy(dec!(0.0512356), quote, Decimal::ZERO).unwrap();
+        assert_eq!(price, dec!(0.05124));
+        assert!(volume * price <= quote);
+        assert!((volume + dec!(0.00000001)) * price > quote);
+
+        let fee = dec!(0.0026);
+        let (price, volume) = info.round_buy(dec!(0.0512356), quote, fee).unwrap();
+        assert!(volume * price * (Decimal::ONE + fee) <= quote);
+        assert!(matches!(info.round_buy(dec!(0.05), dec!(0.0004), fee), Err(BotError::Rejected(_))));
+    }
+
+    fn limit(bs_type: BsType, volume: &str, price: &str) -> LimitOrder {
+        LimitOrder {
+            bs_type,
+            volume: volume.to_string(),
+            pair: "ETH/BTC".to_string(),
+            price: price.to_string(),
+            oflags: Default::default(),
+        }
+    }
+
+    fn balances(quote: Decimal, base: Decimal) -> HashMap<String, Decimal> {
+        HashMap::from([("XXBT".to_string(), quote), ("XETH".to_string(), base)])
+    }
+
+    fn placed(exchange: &mut SimulatedExchange, order: LimitOrder) -> OrderState {
+        let txid = exchange.add_limit_order(order).unwrap().txid[0].clone();
+        exchange.query_order(&txid).unwrap()
+    }
+
+    #[test]
+    fn mock_buys_fill_at_the_limit_with_the_maker_fee_in_quote() {
+        let fees = Fees::from_config();
+        let mut exchange = SimulatedExchange::new(balances(dec!(1), dec!(0)));
+        let order = placed(&mut exchange, limit(BsType::Buy, "10", "0.05"));
+        assert_eq!(order.status, OrderStatus::Closed);
+        assert_eq!((order.volume_executed, order.avg_price), (dec!(10), dec!(0.05)));
+        assert_eq!(order.fee, dec!(0.5) * fees.maker);
+        let balance = exchange.get_account_balance().unwrap();
+        assert_eq!(balance["XETH"], dec!(10));
+        assert_eq!(balance["XXBT"], dec!(0.5) - dec!(0.5) * fees.maker);
+    }
+
+    #[test]
+    fn sells_pay_the_fee_out_of_the_proceeds() {
+        let fees = Fees::from_config();
+        let mut exchange = SimulatedExchange::new(balances(dec!(0), dec!(10)));
+        let order = placed(&mut exchange, limit(BsType::Sell, "10", "0.05"));
+        let balance = exchange.get_account_balance().unwrap();
+        assert_eq!(balance["XETH"], Decimal::ZERO);
+        assert_eq!(balance["XXBT"], dec!(0.5) - order.fee);
+        assert_eq!(order.fee, dec!(0.5) * fees.maker);
+    }
+
+    #[test]
+    fn buys_need_the_fee_on_top() {
+        let fees = Fees::from_config();
+        let mut exchange = SimulatedExchange::new(balances(dec!(0.5), dec!(0)));
+        let refused = exchange.add_limit_order(limit(BsType::Buy, "10", "0.05"));
+        assert!(matches!(refused, Err(Error::KrakenErrors(errors)) if errors[0] == "EOrder:Insufficient funds"));
+        assert!(exchange.get_open_orders().unwrap().is_empty());
+
+        let quote = dec!(0.5) * (Decimal::ONE + fees.maker);
+        let mut exchange = SimulatedExchange::new(balances(quote, Decimal::ZERO));
+        placed(&mut exchange, limit(BsType::Buy, "10", "0.05"));
+        assert_eq!(exchange.get_account_balance().unwrap()["XXBT"], Decimal::ZERO);
+    }
+
+    #[test]
+    fn mock_market_orders_pay_the_taker_fee() {
+        let fees = Fees::from_config();
+        let mut exchange = SimulatedExchange::new(balances(dec!(0), dec!(10)));
+        exchange.on_book("ETH/XBT", dec!(0.049), dec!(0.051));
+        let market = MarketOrder {
+            bs_type: BsType::Sell,
+            volume: "10".to_string(),
+            pair: "ETH/BTC".to_string(),
+            oflags: Default::default(),
+        };
+        let txid = exchange.add_market_order(market).unwrap().txid[0].clone();
+        let order = exchange.query_order(&txid).unwrap();
+        assert_eq!(order.avg_price, dec!(0.049));
+        assert_eq!(order.fee, dec!(0.49) * fees.taker);
+    }
+
+    #[test]
+    fn paper_orders_rest_until_the_book_crosses() {
+        let fees = Fees::from_config();
+        let mut exchange = SimulatedExchange { balance_file: None, ..SimulatedExchange::paper(balances(dec!(1), dec!(0)), "") };
+        exchange.on_book("ETH/XBT", dec!(0.049), dec!(0.051));
+        let resting = placed(&mut exchange, limit(BsType::Buy, "10", "0.05"));
+        assert_eq!(resting.status, OrderStatus::Open);
+
+        // the ask only touching another price leaves it on the book
+        exchange.on_book("ETH/XBT", dec!(0.049), dec!(0.0501));
+        assert_eq!(exchange.query_order(&resting.txid).unwrap().status, OrderStatus::Open);
+
+        exchange.on_book("ETH/XBT", dec!(0.048), dec!(0.0495));
+        let filled = exchange.query_order(&resting.txid).unwrap();
+        assert_eq!(filled.status, OrderStatus::Closed);
+        // it rested, a maker, and got the better ask
+        assert_eq!(filled.avg_price, dec!(0.0495));
+        assert_eq!(filled.fee, dec!(0.495) * fees.maker);
+    }
+
+    #[test]
+    fn paper_orders_crossing_on_placement_are_takers() {
+        let fees = Fees::from_config();
+        let mut exchange = SimulatedExchange { balance_file: None, ..SimulatedExchange::paper(balances(dec!(0), dec!(10)), "") };
+        exchange.on_book("ETH/XBT", dec!(0.052), dec!(0.053));
+        let order = placed(&mut exchange, limit(BsType::Sell, "10", "0.05"));
+        assert_eq!(order.status, OrderStatus::Closed);
+        // filled at the better bid
+        assert_eq!(order.avg_price, dec!(0.052));
+        assert_eq!(order.fee, dec!(0.52) * fees.taker);
+    }
+}
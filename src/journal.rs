@@ -0,0 +1,246 @@
+use chrono::Local;
+use once_cell::sync::OnceCell;
+use rusqlite::{params, Connection};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use crate::config::config;
+use crate::state::OrderSide;
+use crate::{get_current_quote_price, time};
+
+/**
+ * Every hop decision and what became of its orders, one row per event.
+ * Rows of one order share its txid, the gain is recorded with the decision
+ * and the placement. Decimals are stored as text so nothing is rounded,
+ * the fiat price of the quote asset is kept for fiat returns.
+ */
+static JOURNAL: OnceCell<Mutex<Connection>> = OnceCell::new();
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS journal (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp INTEGER NOT NULL,
+    event TEXT NOT NULL,
+    pair TEXT NOT NULL,
+    side TEXT,
+    txid TEXT,
+    price TEXT,
+    volume TEXT,
+    fee TEXT,
+    gain TEXT,
+    net_gain TEXT,
+    reason TEXT NOT NULL,
+    fiat_price TEXT
+);
+CREATE INDEX IF NOT EXISTS journal_txid ON journal (txid);
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Decision,
+    Placed,
+    PlacementFailed,
+    Filled,
+    PartiallyFilled,
+    Cancelled,
+    Expired,
+    Aborted,
+    // a decided hop the order manager would not place
+    Refused,
+    // answers to a hop proposal in approval mode
+    Approved,
+    Rejected,
+    ProposalExpired,
+}
+
+impl Event {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::Decision => "decision",
+            Event::Placed => "placed",
+            Event::PlacementFailed => "placement_failed",
+            Event::Filled => "filled",
+            Event::PartiallyFilled => "partially_filled",
+            Event::Cancelled => "cancelled",
+            Event::Expired => "expired",
+            Event::Aborted => "aborted",
+            Event::Refused => "refused",
+            Event::Approved => "approved",
+            Event::Rejected => "rejected",
+            Event::ProposalExpired => "proposal_expired",
+        }
+    }
+}
+
+/**
+ * One journal row. Prices are base/quote, the fee is in the quote asset.
+ */
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub event: Event,
+    pub side: Option<OrderSide>,
+    pub txid: Option<String>,
+    pub price: Option<Decimal>,
+    pub volume: Option<Decimal>,
+    pub fee: Option<Decimal>,
+    pub gain: Option<Decimal>,
+    pub net_gain: Option<Decimal>,
+    pub reason: String,
+}
+
+impl Entry {
+    pub fn new(event: Event, reason: impl Into<String>) -> Self {
+        Entry {
+            event,
+            side: None,
+            txid: None,
+            price: None,
+            volume: None,
+            fee: None,
+            gain: None,
+            net_gain: None,
+            reason: reason.into(),
+        }
+    }
+}
+
+/**
+ * Opens or creates the journal, `None` keeps it in memory
+ */
+pub fn open(file: Option<&str>) -> rusqlite::Result<()> {
+    let connection = match file {
+        Some(file) => Connection::open(file)?,
+        None => Connection::open_in_memory()?,
+    };
+    connection.execute_batch(SCHEMA)?;
+    // journals from before the fiat price was recorded
+    if connection.prepare("SELECT fiat_price FROM journal LIMIT 0").is_err() {
+        connection.execute_batch("ALTER TABLE journal ADD COLUMN fiat_price TEXT")?;
+    }
+    let _ = JOURNAL.set(Mutex::new(connection));
+    Ok(())
+}
+
+/**
+ * Writes the entry, a failing journal is logged but never stops trading
+ */
+pub fn record(entry: Entry) {
+    let journal = match JOURNAL.get() {
+        Some(journal) => journal,
+        None => return,
+    };
+    let text = |value: Option<Decimal>| value.map(|value| value.to_string());
+    let side = entry.side.map(|side| match side {
+        OrderSide::Buy => "buy",
+        OrderSide::Sell => "sell",
+    });
+    let fiat_price = get_current_quote_price();
+    let result = journal.lock().unwrap().execute(
+        "INSERT INTO journal (timestamp, event, pair, side, txid, price, volume, fee, gain, net_gain, reason, fiat_price)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            Local::now().timestamp(),
+            entry.event.as_str(),
+            config().market.order_pair,
+            side,
+            entry.txid,
+            text(entry.price),
+            text(entry.volume),
+            text(entry.fee),
+            text(entry.gain),
+            text(entry.net_gain),
+            entry.reason,
+            text(fiat_price),
+        ],
+    );
+    if let Err(e) = result {
+        println!("[{} | JOURNAL] Could not record {:?}: {}", time(), entry, e);
+    }
+}
+
+/**
+ * Executed volume of one order, from the row that closed it
+ */
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub timestamp: i64,
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub volume: Decimal,
+    pub fee: Decimal,
+    pub fiat_price: Option<Decimal>,
+}
+
+fn decimal(value: Option<String>) -> Option<Decimal> {
+    value.and_then(|value| Decimal::from_str(&value).ok())
+}
+
+/**
+ * Every fill of the configured pair, oldest first
+ */
+pub fn fills() -> rusqlite::Result<Vec<Fill>> {
+    let journal = match JOURNAL.get() {
+        Some(journal) => journal,
+        None => return Ok(Vec::new()),
+    };
+    let connection = journal.lock().unwrap();
+    let mut statement = connection.prepare(
+        "SELECT timestamp, side, price, volume, fee, fiat_price FROM journal
+         WHERE pair = ?1 AND event IN ('filled', 'partially_filled', 'cancelled', 'expired', 'aborted')
+         ORDER BY id",
+    )?;
+    let rows = statement.query_map(params![config().market.order_pair], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, Option<String>>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, Option<String>>(3)?,
+            row.get::<_, Option<String>>(4)?,
+            row.get::<_, Option<String>>(5)?,
+        ))
+    })?;
+    let mut fills = Vec::new();
+    for row in rows {
+        let (timestamp, side, price, volume, fee, fiat_price) = row?;
+        let side = match side.as_deref() {
+            Some("buy") => OrderSide::Buy,
+            Some("sell") => OrderSide::Sell,
+            _ => continue,
+        };
+        let (price, volume) = match (decimal(price), decimal(volume)) {
+            (Some(price), Some(volume)) if !price.is_zero() && !volume.is_zero() => (price, volume),
+            _ => continue,
+        };
+        fills.push(Fill {
+            timestamp,
+            side,
+            price,
+            volume,
+            fee: decimal(fee).unwrap_or(Decimal::ZERO),
+            fiat_price: decimal(fiat_price),
+        });
+    }
+    Ok(fills)
+}
+
+/**
+ * Latest recorded pair and fiat price, for reports without live books
+ */
+pub fn last_prices() -> rusqlite::Result<Option<(Decimal, Option<Decimal>)>> {
+    let journal = match JOURNAL.get() {
+        Some(journal) => journal,
+        None => return Ok(None),
+    };
+    let connection = journal.lock().unwrap();
+    let mut statement = connection.prepare(
+        "SELECT price, fiat_price FROM journal WHERE pair = ?1 AND price IS NOT NULL ORDER BY id DESC",
+    )?;
+    let mut rows = statement.query(params![config().market.order_pair])?;
+    while let Some(row) = rows.next()? {
+        if let Some(price) = decimal(row.get(0)?).filter(|price| !price.is_zero()) {
+            return Ok(Some((price, decimal(row.get(1)?))));
+        }
+    }
+    Ok(None)
+}
@@ -1,282 +1,522 @@
+mod backtest;
+mod config;
+mod engine;
+mod error;
+mod exchange;
+mod journal;
+mod mock_feed;
+mod pricing;
+mod proposal;
+mod reconnect;
+mod report;
+mod state;
+mod strategy;
+mod telegram;
 
 use chrono::Local;
-use krakenrs::ws::{KrakenWsConfig, KrakenWsAPI, BookData};
-use krakenrs::{KrakenRestConfig, KrakenRestAPI, KrakenCredentials, BsType, LimitOrder, AddOrderResponse, GetOpenOrdersResponse, CancelAllOrdersResponse};
+use krakenrs::ws::{KrakenWsConfig, KrakenPrivateWsConfig, KrakenWsAPI, BookData};
+use krakenrs::{KrakenRestConfig, KrakenCredentials, BsType, LimitOrder, MarketOrder, AddOrderResponse, OrderStatus, TxId};
+use exchange::{Exchange, Fees, KrakenExchange, PairInfo, SimulatedExchange, OrderState};
+use once_cell::sync::OnceCell;
+use strategy::Position;
+use config::{config, PartialFillPolicy};
+use error::BotError;
+use pricing::{BookPrices, BookProblem};
+use state::{BotState, OrderRecord, OrderSide, RecordStatus};
 use once_cell::sync::Lazy;
 use rust_decimal::Decimal;
-use rust_decimal::prelude::ToPrimitive;
-use teloxide::payloads::SendMessageSetters;
-use tokio::runtime::Handle;
-use tokio::task::block_in_place;
 use std::collections::{HashMap, BTreeSet};
-use std::io::{Error, ErrorKind};
-use std::sync::Mutex;
-use std::{
-    time::Duration,
-    thread,
-    env
-};
+use std::sync::{Mutex, MutexGuard};
+use std::env;
 use dotenv::dotenv;
-use teloxide::Bot;
-use teloxide::dispatching::repls::CommandReplExt;
-use teloxide::requests::Requester;
-use teloxide::requests::ResponseResult;
-use teloxide::types::{Message, ChatId, ParseMode};
-use teloxide::utils::command::BotCommands;
-
-/**
- * Last executed order price
- */
-pub static LAST_ORDER: Lazy<Mutex<(f64, bool, )>> = Lazy::new(|| {
-    match serde_any::from_file("last.json") {
-        Ok(hm) => Mutex::new(hm),
-        Err(e) => panic!("No price history! Can't calculate gainz!: {}", e.to_string()),
-    }
-});
-pub static LAST_COMPLETED_ORDER: Lazy<Mutex<(f64, bool, )>> = Lazy::new(|| {
-    match serde_any::from_file("last_completed.json") {
-        Ok(hm) => Mutex::new(hm),
-        Err(e) => panic!("No price history of last completed order!: {}", e.to_string()),
-    }
-});
-// BTC, ETH, ETH/BTC
-pub static CURRENT_PRICES: Lazy<Mutex<(Option<f64>, Option<f64>, Option<f64>)>> = Lazy::new(|| {
-    Mutex::new((None, None, None))
-});
-
-pub static REST_API: Lazy<Mutex<KrakenRestAPI>> = Lazy::new(|| {
-    Mutex::new(block_in_place(|| setup_rest()))
-});
-pub static TO_BTC: f64 = 0.03;
-pub static TO_ETH: f64 = 0.02;
-
-#[derive(Debug)]
-enum Position {
-    Btc,
-    Eth,
-    None,
+use telegram::{notify_order_resolved_telegram, send_telegram_message};
+use teloxide::utils::markdown;
+
+/**
+ * Last placed and last completed order, loaded in main
+ */
+pub static STATE: Lazy<Mutex<BotState>> = Lazy::new(|| Mutex::new(BotState::default()));
+// bid, ask and mid of every subscribed pair, keyed by pair name
+pub static PRICES: Lazy<Mutex<HashMap<String, BookPrices>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// books that can't be traded on right now, trading is suspended while any is set
+pub static BOOK_PROBLEMS: Lazy<Mutex<HashMap<String, BookProblem>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+pub static FEES: Lazy<Mutex<Fees>> = Lazy::new(|| Mutex::new(Fees::from_config()));
+
+pub static EXCHANGE: OnceCell<Mutex<Box<dyn Exchange>>> = OnceCell::new();
+pub static MODE: OnceCell<TradingMode> = OnceCell::new();
+// precision and minimum order of the base/quote pair, loaded in main
+pub static PAIR_INFO: OnceCell<PairInfo> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TradingMode {
+    Live,
+    // in-memory exchange, instant fills
+    Mock,
+    // in-memory exchange, filled against live order books
+    Paper,
 }
 
 #[tokio::main]
 async fn main() {
-    check_env();
-    let _thread_handle = thread::spawn(|| { run_bot(); });
-    let mut ws = block_in_place(|| setup_ws());
+    // before the config, KRAKEN_BOT_* overrides may come from .env
+    dotenv().ok();
+    let args: Vec<String> = env::args().collect();
+    let args = match config::init(&args) {
+        Ok(rest) => rest,
+        Err(e) => {
+            println!("[{} | CONFIG] {}", time(), e);
+            std::process::exit(1);
+        }
+    };
+    if config().timers.reconnect_timer_secs.is_some() {
+        println!("[{} | CONFIG] timers.reconnect_timer_secs is no longer used, see [reconnect]", time());
+    }
+    if args.get(1).map(|arg| arg.as_str()) == Some("backtest") {
+        backtest::run(&args[2..]);
+        return;
+    }
+    if args.get(1).map(|arg| arg.as_str()) == Some("report") {
+        let _ = MODE.set(parse_mode(&args));
+        load_journal();
+        report::run();
+        return;
+    }
 
-    let mut balance = match get_account_balance() {
-        Some(balance) => balance,
-        None => panic!("Couldn't get account balance!"),
-    };
-    let mut balance_stained = false;
-    let reconnect_timer = 30; // reconnect timer in seconds
-    let mut time_to_wait_in_millis = 5000;
-    let order_abort_timeout = 60; // in loop resolutons (30s * X)
-    let mut order_abort_count = 0;
-    /*
-     * reconnection loop 
-     */
-    loop {
-        /*
-        * main loop
-        */
-        loop {
-
-            /*
-            * stop loop on stream closed 
-            */
-            if ws.stream_closed() { 
-                println!("Stream closed");
-                notify_stream_close();
-                break; 
-            }
+    let mode = parse_mode(&args);
+    let telegram = check_env(mode);
+    engine::blocking(move || {
+        setup_exchange(mode);
+        load_state();
+        load_journal();
+        load_fees();
+        load_pair_info();
+    }).await;
+    engine::run(telegram).await;
+}
 
-            /*
-            * slow down the loop
-            */ 
-            thread::sleep(Duration::from_millis(time_to_wait_in_millis));
-            /*
-            *  check order resolution update 
-            *  NOTE: must be above balance update, so it 
-            *  updates after order resolution
-            */
-            if is_waiting_order_resolution() {
-                time_to_wait_in_millis = 30000;
-                let orders = get_open_orders();
-                match orders {
-                    Ok(ord) => {
-                        if ord.open.len() == 0 {
-                            complete_last_order();
-                            time_to_wait_in_millis = 5000;
-                            order_abort_count = 0;
-                            notify_order_completed_telegram();
-                        }
-                    },
-                    Err(e) => { println!("[{} | ORDER RESOLUTION WAIT] Could not fetch open orders: {}", time(), e.to_string())},
-                }
-                println!("[{} | ORDER RESOLUTION WAIT] Waiting for order to resolve", time());
-                if order_abort_count > order_abort_timeout {
-                    abort_order();
-                } else {
-                    order_abort_count += 1;
-                }
-                continue;
-            }
 
-            /*
-            * update prices on tick
-            */
-            update_prices(block_in_place(|| ws.get_all_books()));
-
-            /*
-            * get my balance
-            */
-            if balance_stained {
-                balance = match get_account_balance() {
-                    Some(bal) => bal,
-                    None => panic!("[{} | LOOP] Error getting account balance", time()),
-                };
-                balance_stained = false;
-            }
 
-            /*
-            * calc my position from balance 
-            */
-            let position = get_my_position(&balance);
-
-            /*
-            * get current gain
-            */
-            let gain = match calculate_gain(&position) {
-                Some(gain) => gain,
-                None => panic!("[{} | LOOP] Error calculating gain!", time()),
-            };
-            println!("GAIN: {:#?}", gain);
-
-            /*
-            * hop strat eval
-            */
-            let should_hop = match position {
-                Position::Btc => gain > TO_ETH,
-                Position::Eth => gain > TO_BTC,
-                Position::None => false,
-            };
+/**
+ * Telegram is optional for --mock runs, without it messages are only logged
+ */
+fn check_env(mode: TradingMode) -> bool {
+    let configured = env::var("TELEGRAM_REPORT_CHAT_ID").is_ok() && env::var("TELEGRAM_BOT_TOKEN").is_ok();
+    if mode == TradingMode::Mock && !configured {
+        println!("[{} | SETUP] Telegram is not configured, messages are only logged", time());
+        return false;
+    }
+    let _report_chat = env::var("TELEGRAM_REPORT_CHAT_ID").expect("$TELEGRAM_REPORT_CHAT_ID is not set").parse::<i64>().unwrap();
+    let token = env::var("TELEGRAM_BOT_TOKEN").expect("$TELEGRAM_BOT_TOKEN is not set");
+    env::set_var("TELOXIDE_TOKEN", token);
+    true
+}
 
-            /*
-            * execute hop
-            */
-            if should_hop {
-                match execute_hop(&position, &balance) {
-                    Ok((order_response, price)) => {
-                        println!("[{} | EXECUTED TRADE] Order placed: {:#?}", time(), order_response); 
-                        update_last_order(price);
-                        balance_stained = true;
-                        notify_order_placed_telegram(&order_response, &price, &gain, &position);
-                    },
-                    Err(e) => panic!("[{} | EXECUTED TRADE] PANIC: {}", time(), e.to_string())
-                };
-            }
+fn update_last_order(order: OrderRecord) {
+    {
+        let mut state = STATE.lock().unwrap();
+        state.last_order = Some(order);
+    }
+    save_state();
+}
 
+/**
+ * Records how the last order ended. Only a complete fill moves the
+ * reference price, otherwise gains are measured from the last completed order.
+ */
+fn finish_last_order(status: RecordStatus, order: Option<&OrderState>) {
+    {
+        let mut state = STATE.lock().unwrap();
+        if let Some(last) = state.last_order.as_mut() {
+            last.status = status;
+            if let Some(order) = order {
+                last.executed_volume = Some(order.volume_executed);
+                if !order.avg_price.is_zero() {
+                    last.avg_price = Some(order.avg_price);
+                }
+            }
         }
-        thread::sleep(Duration::from_secs(reconnect_timer));
-        ws = block_in_place(|| setup_ws());
-        if !ws.stream_closed() {
-            notify_successful_reconnect();
+        if status == RecordStatus::Filled {
+            state.last_completed = state.last_order.clone();
         }
     }
+    save_state();
+}
+
+/**
+ * Journals a decision to hop or an answer to a proposed one,
+ * at the price it would order at
+ */
+fn journal_hop(event: journal::Event, position: &Position, gain: Decimal, net_gain: Decimal, reason: String) {
+    journal::record(journal::Entry {
+        side: hop_side(position),
+        price: get_execution_price(position),
+        gain: Some(gain),
+        net_gain: Some(net_gain),
+        ..journal::Entry::new(event, reason)
+    });
 }
 
+fn threshold_reason(position: &Position, net_gain: Decimal) -> String {
+    let threshold = strategy::hop_threshold(position, &config().strategy).unwrap_or(Decimal::ZERO);
+    format!("net gain {} above threshold {}", net_gain.round_dp(6), threshold)
+}
 
+/**
+ * Journals what became of the last order, with its fill so far
+ */
+fn journal_order(event: journal::Event, txid: Option<String>, order: Option<&OrderState>, reason: String) {
+    let side = STATE.lock().unwrap().last_order.as_ref().and_then(|last| last.side);
+    journal::record(journal::Entry {
+        side,
+        txid,
+        price: order.map(|order| order.avg_price),
+        volume: order.map(|order| order.volume_executed),
+        fee: order.map(|order| order.fee),
+        ..journal::Entry::new(event, reason)
+    });
+}
 
+/**
+ * Journals a newly placed order or why it could not be placed
+ */
+pub fn journal_placement(placed: &Result<(AddOrderResponse, OrderRecord), BotError>, gain: Option<Decimal>, net_gain: Option<Decimal>, reason: &str) {
+    let entry = match placed {
+        Ok((_, order)) => journal::Entry {
+            side: order.side,
+            txid: order.txid.clone(),
+            price: Some(order.price),
+            volume: order.volume,
+            gain,
+            net_gain,
+            ..journal::Entry::new(journal::Event::Placed, reason)
+        },
+        Err(e) => journal::Entry {
+            gain,
+            net_gain,
+            ..journal::Entry::new(journal::Event::PlacementFailed, format!("{}: {}", reason, e))
+        },
+    };
+    journal::record(entry);
+}
 
-fn check_env() {
-    dotenv().ok();
-    let _report_chat = env::var("TELEGRAM_REPORT_CHAT_ID").expect("$TELEGRAM_REPORT_CHAT_ID is not set").parse::<i64>().unwrap();
-    let token = env::var("TELEGRAM_BOT_TOKEN").expect("$TELEGRAM_BOT_TOKEN is not set");
-    env::set_var("TELOXIDE_TOKEN", token);
+fn trading_paused() -> bool {
+    STATE.lock().unwrap().paused
 }
 
-fn update_last_order(price: f64) {
+/**
+ * Returns false when trading already was in that state
+ */
+fn set_paused(paused: bool) -> bool {
     {
-        let mut last = LAST_ORDER.lock().unwrap();
-        last.0 = price;
-        last.1 = false;
+        let mut state = STATE.lock().unwrap();
+        if state.paused == paused {
+            return false;
+        }
+        state.paused = paused;
+    }
+    save_state();
+    println!("[{} | TRADING] {}", time(), if paused { "Paused" } else { "Resumed" });
+    true
+}
+
+fn has_prior_fill() -> bool {
+    let state = STATE.lock().unwrap();
+    state.last_order.as_ref().is_some_and(|last| last.prior_fill.is_some())
+}
+
+fn pending_txid() -> Option<TxId> {
+    let state = STATE.lock().unwrap();
+    state.last_order.as_ref().and_then(|last| last.txid.clone())
+}
+
+/**
+ * Outcome of an order, None while it is still on the book. A re-placed
+ * remainder that ends without a fill of its own still carries the
+ * earlier partial fill of its hop.
+ */
+fn order_outcome(order: &OrderState, prior_fill: bool) -> Option<RecordStatus> {
+    match order.status {
+        OrderStatus::Pending | OrderStatus::Open => None,
+        _ => Some(closed_outcome(order, prior_fill)),
+    }
+}
+
+/**
+ * How an order that left the book, or is being cancelled, ended
+ */
+fn closed_outcome(order: &OrderState, prior_fill: bool) -> RecordStatus {
+    match order.status {
+        _ if order.volume_executed >= order.volume => RecordStatus::Filled,
+        _ if !order.volume_executed.is_zero() || prior_fill => RecordStatus::PartiallyFilled,
+        OrderStatus::Expired => RecordStatus::Expired,
+        _ => RecordStatus::Cancelled,
+    }
+}
+
+/**
+ * Looks the pending order up by txid. Returns it once it left the book.
+ */
+fn check_last_order() -> Result<Option<OrderState>, BotError> {
+    let txid = match pending_txid() {
+        Some(txid) => txid,
+        None => return check_untracked_order(),
+    };
+    let order = {
+        let api = exchange();
+        api.query_order(&txid)?
+    };
+    match order_outcome(&order, has_prior_fill()) {
+        Some(_) => Ok(Some(order)),
+        None => Ok(None),
+    }
+}
+
+/**
+ * Orders from before txids were recorded can only be
+ * judged by the open orders list being empty
+ */
+fn check_untracked_order() -> Result<Option<OrderState>, BotError> {
+    let orders = get_open_orders()?;
+    if !orders.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(OrderState {
+        txid: "untracked".to_string(),
+        status: OrderStatus::Closed,
+        volume: Decimal::ZERO,
+        volume_executed: Decimal::ZERO,
+        avg_price: Decimal::ZERO,
+        fee: Decimal::ZERO,
+    }))
+}
+
+fn resolve_last_order(order: &OrderState) {
+    let status = order_outcome(order, has_prior_fill()).unwrap_or(RecordStatus::Cancelled);
+    println!("[{} | ORDER RESOLUTION] Order {} resolved as {:?}", time(), order.txid, status);
+    let event = match status {
+        RecordStatus::Filled => journal::Event::Filled,
+        RecordStatus::PartiallyFilled => journal::Event::PartiallyFilled,
+        RecordStatus::Expired => journal::Event::Expired,
+        _ => journal::Event::Cancelled,
+    };
+    let txid = pending_txid().unwrap_or_else(|| order.txid.clone());
+    journal_order(event, Some(txid), Some(order), "closed on the exchange".to_string());
+    finish_last_order(status, Some(order));
+    let follow_up = match status {
+        RecordStatus::PartiallyFilled => handle_partial_fill(config().strategy.partial_fill),
+        _ => String::new(),
+    };
+    notify_order_resolved_telegram(status, order, &follow_up);
+}
+
+/**
+ * Deals with the unfilled rest of a partially filled hop.
+ * Returns what was done, for the notification.
+ */
+fn handle_partial_fill(policy: PartialFillPolicy) -> String {
+    let last = match STATE.lock().unwrap().last_order.clone() {
+        Some(last) if last.status == RecordStatus::PartiallyFilled => last,
+        _ => return String::new(),
+    };
+    let remainder = last.volume.unwrap_or(Decimal::ZERO) - last.executed_volume.unwrap_or(Decimal::ZERO);
+    let placed = match policy {
+        PartialFillPolicy::Accept => return accept_split(&last),
+        PartialFillPolicy::Requote => place_remainder(&last, remainder, false),
+        PartialFillPolicy::Market => place_remainder(&last, remainder, true),
+    };
+    journal_placement(&placed, None, None, &format!("remainder of a partial fill, {:?} policy", policy));
+    match placed {
+        Ok((order_response, order)) => {
+            println!("[{} | PARTIAL FILL] Remainder placed: {:#?}", time(), order_response);
+            let placed_volume = order.volume.unwrap_or(remainder);
+            update_last_order(order);
+            format!("Placed the remaining {:.5}: {}", placed_volume, order_response.descr.order)
+        },
+        Err(e) => {
+            println!("[{} | PARTIAL FILL] {}", time(), e);
+            format!("Could not place the remaining {:.5}, keeping the split. {}", remainder, accept_split(&last))
+        },
+    }
+}
+
+/**
+ * Keeps the split balance. The hop counts as done once most of it filled,
+ * which is also the side get_my_position will report.
+ */
+fn accept_split(last: &OrderRecord) -> String {
+    let fill = last.total_fill();
+    if fill.volume * Decimal::TWO < last.hop_volume() {
+        return "Accepted the split, gains are measured from the last completed order.".to_string();
+    }
+    STATE.lock().unwrap().last_completed = Some(last.clone());
+    save_state();
+    format!("Accepted the split, the hop counts as done at {:.5}.", fill.avg_price)
+}
+
+/**
+ * Same side as the partially filled order, for the volume it left open
+ */
+fn place_remainder(last: &OrderRecord, remainder: Decimal, at_market: bool) -> Result<(AddOrderResponse, OrderRecord), BotError> {
+    let market = config().market;
+    let side = match last.side {
+        Some(side) => side,
+        None => return Err(BotError::MissingData("side of the partially filled order".to_string())),
+    };
+    let bs_type = match side {
+        OrderSide::Buy => BsType::Buy,
+        OrderSide::Sell => BsType::Sell,
+    };
+    let price = match get_order_price(side) {
+        Some(pr) => pr,
+        None => return Err(BotError::MissingData(format!("{} price", market.pair()))),
+    };
+    // the price may have risen since, never buy more than the quote left pays for
+    let (price, remainder) = match side {
+        OrderSide::Buy => {
+            let quote = get_account_balance()?.get(&market.quote_balance_key).copied().unwrap_or(Decimal::ZERO);
+            let fee = if at_market { FEES.lock().unwrap().taker } else { hop_fee() };
+            let (price, affordable) = pair_info().round_buy(price, quote, fee)?;
+            round_order(price, remainder.min(affordable))?
+        },
+        OrderSide::Sell => round_order(price, remainder)?,
+    };
+    let volume = remainder.to_string();
+    let pair = market.order_pair;
+    let oflags = BTreeSet::new();
+
+    let mut api = exchange();
+    let response = if at_market {
+        api.add_market_order(MarketOrder { bs_type, volume, pair, oflags })
+    } else {
+        api.add_limit_order(LimitOrder { bs_type, volume, pair, price: price.to_string(), oflags })
+    };
+    match response {
+        Ok(r) => {
+            let order = OrderRecord {
+                txid: r.txid.first().cloned(),
+                side: Some(side),
+                price,
+                volume: Some(remainder),
+                timestamp: Local::now().timestamp(),
+                status: RecordStatus::Pending,
+                executed_volume: None,
+                avg_price: None,
+                prior_fill: Some(last.total_fill()),
+            };
+            Ok((r, order))
+        },
+        Err(e) => Err(BotError::from(e)),
     }
-    save_last_order();
 }
 
-fn complete_last_order() {
+/**
+ * Without any order history the current price becomes the reference
+ * gains are measured against. Returns false while there is no reference.
+ */
+fn ensure_reference_price() -> bool {
+    if get_last_trade().is_some() {
+        return true;
+    }
+    let price = match get_current_relative_price() {
+        Some(price) => price,
+        None => return false,
+    };
+    let reference = OrderRecord {
+        txid: None,
+        side: None,
+        price,
+        volume: None,
+        timestamp: Local::now().timestamp(),
+        status: RecordStatus::Filled,
+        executed_volume: None,
+        avg_price: None,
+        prior_fill: None,
+    };
     {
-        let mut last = LAST_ORDER.lock().unwrap();
-        let mut last_completed = LAST_COMPLETED_ORDER.lock().unwrap();
-        last.1 = true;
-        last_completed.0 = last.0;
-        last_completed.1 = last.1;
+        let mut state = STATE.lock().unwrap();
+        state.last_order = Some(reference.clone());
+        state.last_completed = Some(reference);
     }
-    save_last_order();
-    save_last_completed_order();
+    save_state();
+    println!("[{} | STATE] No order history, using {} as the reference price", time(), price);
+    send_telegram_message(format!("No order history found, measuring gains from the current price `{}` 📏", price));
+    true
 }
 
-fn get_open_orders() -> Result<GetOpenOrdersResponse, krakenrs::Error> {
-    let api = REST_API.lock().unwrap();
-    block_in_place(|| api.get_open_orders(None))
+fn get_open_orders() -> Result<HashMap<TxId, OrderState>, BotError> {
+    let api = exchange();
+    Ok(api.get_open_orders()?)
 }
 
 fn is_waiting_order_resolution() -> bool {
-    let last = LAST_ORDER.lock().unwrap();
-    !last.1
+    let state = STATE.lock().unwrap();
+    match &state.last_order {
+        Some(last) => last.status == RecordStatus::Pending,
+        None => false,
+    }
 }
 
-fn converted_volume(position: &Position, volume: f64) -> f64 {
+/**
+ * Order volumes are in the base asset, `price` is the base/quote limit price
+ */
+fn converted_volume(position: &Position, volume: Decimal, price: Decimal) -> Decimal {
     match position {
-        Position::Btc => volume / get_currnet_relative_price().unwrap(),
-        Position::Eth => volume,
-        Position::None => 0.,
+        Position::Quote => volume.checked_div(price).unwrap_or(Decimal::ZERO),
+        Position::Base => volume,
+        Position::None => Decimal::ZERO,
     }
 }
 
-fn execute_hop(position: &Position, balance: &HashMap<String, Decimal>) -> Result<(AddOrderResponse, f64), Error> {
-    let bs_type = match position {
-        Position::Btc => BsType::Buy,
-        Position::Eth => BsType::Sell,
-        Position::None => return Err(Error::new(
-            ErrorKind::Other, 
-            format!("[{} | EXECUTE HOP] No position specified!", time())
-        )),
-    };
+fn hop_side(position: &Position) -> Option<OrderSide> {
+    match position {
+        Position::Quote => Some(OrderSide::Buy),
+        Position::Base => Some(OrderSide::Sell),
+        Position::None => None,
+    }
+}
 
-    let volume_option = match position {
-        Position::Btc => balance.get("XXBT"),
-        Position::Eth => balance.get("XETH"),
-        Position::None => return Err(Error::new(
-            ErrorKind::Other, 
-            format!("[{} | EXECUTE HOP] No position specified!", time())
-        )),
+/**
+ * Side, limit price and volume of the order that hops out of the position
+ */
+fn hop_order(position: &Position, balance: &HashMap<String, Decimal>) -> Result<(OrderSide, Decimal, Decimal), BotError> {
+    let market = config().market;
+    let (side, balance_key) = match position {
+        Position::Quote => (OrderSide::Buy, &market.quote_balance_key),
+        Position::Base => (OrderSide::Sell, &market.base_balance_key),
+        Position::None => return Err(BotError::MissingData("position".to_string())),
     };
 
-    let base_volume = match volume_option {
-        Some(vol) => vol.to_f64().unwrap(),
-        None => return Err(Error::new(
-            ErrorKind::Other, 
-            format!("[{} | EXECUTE HOP] Error geting volume from balance!", time())
-        )),
+    let held = match balance.get(balance_key) {
+        Some(vol) => *vol,
+        None => return Err(BotError::MissingData(format!("{} balance", balance_key))),
     };
 
-    let volume = converted_volume(position, base_volume).to_string();
+    let book_price = match get_order_price(side) {
+        Some(pr) => pr,
+        None => return Err(BotError::MissingData(format!("{} price", market.pair()))),
+    };
 
-    let pair = "ETH/BTC".to_string();
+    let (price, volume) = match side {
+        OrderSide::Buy => pair_info().round_buy(book_price, held, hop_fee())?,
+        OrderSide::Sell => round_order(book_price, held)?,
+    };
+    Ok((side, price, volume))
+}
 
-    let price_float = match get_currnet_relative_price() {
-        Some(pr) => round_to_precision(pr),
-        None => return Err(Error::new(
-            ErrorKind::Other, 
-            format!("[{} | EXECUTE HOP] Error geting price from state!", time())
-        )),
+fn execute_hop(side: OrderSide, price_decimal: Decimal, volume_decimal: Decimal) -> Result<(AddOrderResponse, OrderRecord), BotError> {
+    let market = config().market;
+    let bs_type = match side {
+        OrderSide::Buy => BsType::Buy,
+        OrderSide::Sell => BsType::Sell,
     };
-    let price = price_float.to_string();
+    let price = price_decimal.to_string();
+    let volume = volume_decimal.to_string();
+
+    let pair = market.order_pair;
 
     let oflags = BTreeSet::new();
 
-    let api = REST_API.lock().unwrap();
+    let mut api = exchange();
     let limit_order = LimitOrder {
         bs_type,
         volume,
@@ -284,25 +524,61 @@ fn execute_hop(position: &Position, balance: &HashMap<String, Decimal>) -> Resul
         price,
         oflags,
     };
-    block_in_place(|| match api.add_limit_order(
-        limit_order, 
-        None, 
-        false
-    ) {
-        Ok(r) => Ok((r, price_float)),
-        Err(e) => Err(Error::new(
-            ErrorKind::Other, 
-            format!("[{} | EXECUTE HOP] Error executing transaction: {}", time(), e.to_string())
-        )),
-    })
+    match api.add_limit_order(limit_order) {
+        Ok(r) => {
+            let order = OrderRecord {
+                txid: r.txid.first().cloned(),
+                side: Some(side),
+                price: price_decimal,
+                volume: Some(volume_decimal),
+                timestamp: Local::now().timestamp(),
+                status: RecordStatus::Pending,
+                executed_volume: None,
+                avg_price: None,
+                prior_fill: None,
+            };
+            Ok((r, order))
+        },
+        Err(e) => Err(BotError::from(e)),
+    }
 }
 
-fn round_to_precision(num: f64) -> f64 {
-    (num * 100000.0).round() / 100000.0
+/**
+ * Rounds price and volume to what the pair accepts and refuses
+ * orders Kraken would reject for being too small
+ */
+fn round_order(price: Decimal, volume: Decimal) -> Result<(Decimal, Decimal), BotError> {
+    pair_info().round_order(price, volume)
 }
 
-fn calculate_gain(position: &Position) -> Option<f64> {
-    let (last_value, last_completed) = get_last_trade();
+fn pair_info() -> PairInfo {
+    PAIR_INFO.get().copied().unwrap_or_else(PairInfo::from_config)
+}
+
+/**
+ * Precision of the base/quote pair from Kraken, the configured one if unavailable
+ */
+fn load_pair_info() {
+    let pair = config().market.pair();
+    let info = {
+        let api = exchange();
+        match api.get_pair_info(&pair) {
+            Ok(info) => info,
+            Err(e) => {
+                println!("[{} | PAIR INFO] Could not fetch {}, using configured precision: {:#?}", time(), pair, e);
+                PairInfo::from_config()
+            }
+        }
+    };
+    println!(
+        "[{} | PAIR INFO] {} price decimals {} tick {} lot decimals {} ordermin {}",
+        time(), pair, info.price_decimals, info.tick_size, info.lot_decimals, info.ordermin
+    );
+    let _ = PAIR_INFO.set(info);
+}
+
+fn calculate_gain(position: &Position) -> Option<Decimal> {
+    let (last_value, last_completed) = get_last_trade()?;
     if !last_completed {
         return None;
     }
@@ -311,417 +587,432 @@ fn calculate_gain(position: &Position) -> Option<f64> {
         return None;
     }
 
-    let current_value = match get_currnet_relative_price() {
-        Some(val) => val,
-        None => return None,
-    };
+    let current_value = get_current_relative_price()?;
 
-    let gain_ratio = (current_value / last_value) - 1.;
+    strategy::gain(position, current_value, last_value)
+}
 
-    match position {
-        Position::Btc => Some(gain_ratio * -1.),
-        Position::Eth => Some(gain_ratio),
-        Position::None => return None,
+/**
+ * Gain after fees at the limit price the pricing policy would place the hop at
+ */
+fn calculate_net_gain(position: &Position) -> Option<Decimal> {
+    let (last_value, last_completed) = get_last_trade()?;
+    if !last_completed {
+        return None;
     }
+
+    let execution_value = get_execution_price(position)?;
+    let gross = strategy::gain(position, execution_value, last_value)?;
+    Some(strategy::net_gain(gross, hop_fee()))
 }
 
-fn calculate_threshold_value(position: &Position) -> Option<f64> {
-    let (last_value, last_completed) = get_last_trade();
+fn calculate_threshold_value(position: &Position) -> Option<Decimal> {
+    let (last_value, last_completed) = get_last_trade()?;
     if !last_completed {
         return None;
     }
 
-    match position {
-        Position::Btc => Some(last_value * (1. - TO_ETH)),
-        Position::Eth => Some(last_value * (1. + TO_BTC)),
-        Position::None => return None,
+    strategy::threshold_value(position, last_value, &config().strategy, hop_fee())
+}
+
+fn get_execution_price(position: &Position) -> Option<Decimal> {
+    get_order_price(hop_side(position)?)
+}
+
+/**
+ * Limit price for the base/quote pair according to the pricing policy
+ */
+fn get_order_price(side: OrderSide) -> Option<Decimal> {
+    let market = config().market;
+    let book = get_book(&market.pair())?;
+    Some(pricing::order_price(side, &book, &config().pricing, pair_info().tick_size))
+}
+
+fn hop_fee() -> Decimal {
+    let fees = FEES.lock().unwrap();
+    if pricing::is_maker(config().pricing.policy) {
+        fees.maker
+    } else {
+        fees.taker
     }
 }
 
-fn get_currnet_relative_price() -> Option<f64> {
-    let prices = CURRENT_PRICES.lock().unwrap();
-    prices.2
+/**
+ * Fee tier of the account, the configured fees when not fetched or unavailable
+ */
+fn load_fees() {
+    let configured = Fees::from_config();
+    let fees = if config().fees.fetch {
+        let api = exchange();
+        match api.get_fees(&config().market.order_pair) {
+            Ok(fees) => fees,
+            Err(e) => {
+                println!("[{} | FEES] Could not fetch fees, using configured ones: {:#?}", time(), e);
+                configured
+            }
+        }
+    } else {
+        configured
+    };
+    println!("[{} | FEES] maker {:.4}% taker {:.4}%", time(), fees.maker * Decimal::ONE_HUNDRED, fees.taker * Decimal::ONE_HUNDRED);
+    *FEES.lock().unwrap() = fees;
+}
+
+fn get_book(pair: &str) -> Option<BookPrices> {
+    PRICES.lock().unwrap().get(pair).copied()
 }
 
-fn get_currnet_eth_price() -> Option<f64> {
-    let prices = CURRENT_PRICES.lock().unwrap();
-    prices.1
+fn get_current_relative_price() -> Option<Decimal> {
+    get_book(&config().market.pair()).map(|book| book.mid)
 }
 
-fn get_currnet_btc_price() -> Option<f64> {
-    let prices = CURRENT_PRICES.lock().unwrap();
-    prices.0
+fn get_current_base_price() -> Option<Decimal> {
+    get_book(&config().market.base_fiat_pair()).map(|book| book.mid)
 }
 
+fn get_current_quote_price() -> Option<Decimal> {
+    get_book(&config().market.quote_fiat_pair()).map(|book| book.mid)
+}
+
+/**
+ * Only books that pass the checks update prices
+ */
 fn update_prices(books: std::collections::BTreeMap<String, BookData>) {
+    let depth = config().market.book_depth;
     for (book_id, book) in books.iter() {
-        let (bids, asks) = extract_bids_and_asks_from_book(book);
-        
-        let min_ask = match asks.iter().min_by(|a, b| a.partial_cmp(b).unwrap()) {
-            Some(ask) => ask,
-            None => panic!(),
-        };
+        let problem = pricing::check_book(book);
+        if problem.is_none() {
+            if let Some(prices) = BookPrices::from_book(book, depth) {
+                PRICES.lock().unwrap().insert(book_id.clone(), prices);
+            }
+            feed_book_to_exchange(book_id, book);
+        }
+        set_book_problem(book_id, problem);
+    }
+}
 
-        let max_bid = match bids.iter().max_by(|a, b| a.partial_cmp(b).unwrap()) {
-            Some(bid) => bid,
-            None => panic!(),
-        };
-        let current_price = (min_ask + max_bid) / 2.;
-        update_price(book_id, current_price);
+/**
+ * Notifies when trading gets suspended by a book and when all books are healthy again
+ */
+fn set_book_problem(pair: &str, problem: Option<BookProblem>) {
+    let mut problems = BOOK_PROBLEMS.lock().unwrap();
+    let previous = match &problem {
+        Some(problem) => problems.insert(pair.to_string(), problem.clone()),
+        None => problems.remove(pair),
+    };
+    match (previous, problem) {
+        (None, Some(problem)) => {
+            println!("[{} | BOOK] {} book {}, trading suspended", time(), pair, problem);
+            send_telegram_message(markdown::escape(&format!("⚠️ Trading suspended, the {} book {}.", pair, problem)));
+        },
+        (Some(_), None) if problems.is_empty() => {
+            println!("[{} | BOOK] {} book recovered, trading resumed", time(), pair);
+            send_telegram_message(markdown::escape("✅ Order books are healthy again, trading resumed."));
+        },
+        _ => (),
     }
 }
 
-fn update_price(book_id: &str, current_price: f64) {
-    let mut prices = CURRENT_PRICES.lock().unwrap();
-    if book_id.eq("ETH/XBT") {
-        prices.2 = Some(current_price);
+/**
+ * Description of what keeps the bot from trading, None while all books are healthy
+ */
+fn book_problems() -> Option<String> {
+    let problems = BOOK_PROBLEMS.lock().unwrap();
+    if problems.is_empty() {
+        return None;
     }
-    if book_id.eq("ETH/EUR") {
-        prices.1 = Some(current_price);
+    let mut described: Vec<String> = problems.iter().map(|(pair, problem)| format!("{} {}", pair, problem)).collect();
+    described.sort();
+    Some(described.join(", "))
+}
+
+/**
+ * Only the simulated exchange fills against our books. Kraken holds the
+ * exchange lock for whole REST calls, so live price updates never take it.
+ */
+fn feed_book_to_exchange(book_id: &str, book: &BookData) {
+    if trading_mode() == TradingMode::Live {
+        return;
     }
-    if book_id.eq("XBT/EUR") {
-        prices.0 = Some(current_price);
+    let best_bid = book.bid.keys().next_back();
+    let best_ask = book.ask.keys().next();
+    if let (Some(bid), Some(ask)) = (best_bid, best_ask) {
+        exchange().on_book(book_id, *bid, *ask);
     }
 }
 
-fn get_account_balance() -> Option<HashMap<String, Decimal>> {
-    let rest = REST_API.lock().unwrap();
-    block_in_place(|| match rest.get_account_balance() {
-        Ok(bal) => Some(bal),
+fn get_account_balance() -> Result<HashMap<String, Decimal>, BotError> {
+    let rest = exchange();
+    match rest.get_account_balance() {
+        Ok(bal) => Ok(bal),
         Err(e) => {
             println!("[{} | GET ACCOUTN BALANCE] Error: {:#?}", time(), e);    
-            None
+            Err(BotError::from(e))
         }
-    })
-}
-
-fn extract_bids_and_asks_from_book(book: &BookData) -> (Vec<f64>, Vec<f64>) {
-    let asks: Vec<f64> = book.ask.keys().filter_map(|entry| entry.to_f64()).collect();
-    let bids: Vec<f64> = book.bid.keys().filter_map(|entry| entry.to_f64()).collect();
-    (bids, asks)
+    }
 }
 
-fn setup_ws() -> KrakenWsAPI {
-    let pairs = vec![
-        "ETH/XBT".to_string(), 
-        "ETH/EUR".to_string(), 
-        "XBT/EUR".to_string()
-    ];
+fn setup_ws() -> Result<KrakenWsAPI, String> {
+    let market = config().market;
     let ws_config = KrakenWsConfig {
-        subscribe_book: pairs.clone(),
-        book_depth: 10,
+        subscribe_book: market.pairs(),
+        book_depth: market.book_depth,
         private: None,
     };
-    KrakenWsAPI::new(ws_config).expect("could not connect to websockets api")
-}
-
-fn setup_rest() -> KrakenRestAPI {
-    let creds = match KrakenCredentials::load_json_file("./creds.json") {
-        Ok(creds) => creds,
-        Err(e) => panic!("SETUP REST PANIC: {}", e.to_string()),
-    };
-    let mut kraken_config = KrakenRestConfig::default();
-    kraken_config.creds = creds;
-    KrakenRestAPI::try_from(kraken_config).expect("could not create kraken rest api")
+    KrakenWsAPI::new(ws_config).map_err(|e| format!("Could not connect: {:?}", e))
 }
 
-fn save_last_order() {
-    let last = LAST_ORDER.lock().unwrap();
-    match serde_any::to_file("last.json", &*last) {
-        Ok(_) => {();},
-        Err(e) => {println!("[{} | ORDER SAVE] Error saving last.json: {:#?}", time(), e);}
+/**
+ * Private feed of our open orders, None when the exchange has
+ * no feed or it is turned off
+ */
+fn setup_private_ws() -> Result<Option<KrakenWsAPI>, String> {
+    if !config().market.private_feed {
+        return Ok(None);
+    }
+    let token = match exchange().get_websockets_token() {
+        Ok(Some(token)) => token,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(format!("Could not get a websockets token: {}", e)),
     };
-}
-
-fn save_last_completed_order() {
-    let last = LAST_COMPLETED_ORDER.lock().unwrap();
-    match serde_any::to_file("last_completed.json", &*last) {
-        Ok(_) => {();},
-        Err(e) => {println!("[{} | ORDER SAVE] Error saving last_completed.json: {:#?}", time(), e);}
+    let ws_config = KrakenWsConfig {
+        subscribe_book: Vec::new(),
+        book_depth: config().market.book_depth,
+        private: Some(KrakenPrivateWsConfig {
+            token,
+            subscribe_open_orders: true,
+        }),
     };
+    match KrakenWsAPI::new(ws_config) {
+        Ok(ws) => Ok(Some(ws)),
+        Err(e) => Err(format!("Could not connect: {:?}", e)),
+    }
 }
 
-fn get_my_position(balance: &HashMap<String, Decimal>) -> Position {
-    let btc_position = match balance.get("XXBT") {
-        Some(pos) => pos.to_f64().unwrap(),
-        None => 0.,
-    };
-
-    let eth_position = match balance.get("XETH") {
-        Some(pos) => pos.to_f64().unwrap(),
-        None => 0.,
-    };
-
-    let btc_eur = get_btc_value(btc_position).unwrap_or(0.);
-    let eth_eur = get_eth_value(eth_position).unwrap_or(0.);
-
-    if btc_eur == 0. && eth_eur == 0. {
-        Position::None
-    } else if btc_eur > eth_eur {
-        Position::Btc
+fn parse_mode(args: &[String]) -> TradingMode {
+    if args.iter().any(|arg| arg == "--paper") {
+        TradingMode::Paper
+    } else if args.iter().any(|arg| arg == "--mock") {
+        TradingMode::Mock
     } else {
-        Position::Eth
+        TradingMode::Live
     }
 }
 
-fn get_last_trade() -> (f64, bool) {
-    let last = LAST_ORDER.lock().unwrap();
-    (last.0, last.1)
+fn trading_mode() -> TradingMode {
+    *MODE.get().unwrap_or(&TradingMode::Live)
 }
 
-fn load_previous_order() {
-    {
-        let mut last = LAST_ORDER.lock().unwrap();
-        let last_completed = LAST_COMPLETED_ORDER.lock().unwrap();
-        last.0 = last_completed.0;
-        last.1 = last_completed.1;
-    }
-    save_last_completed_order();
-}
-
-fn get_btc_value(ammount: f64) -> Option<f64> {
-    let val = {
-        let prices = CURRENT_PRICES.lock().unwrap();
-        match prices.0 {
-            Some(pr) => pr,
-            None => return None,
-        }
+fn setup_exchange(mode: TradingMode) {
+    let exchange: Box<dyn Exchange> = match mode {
+        TradingMode::Live => Box::new(setup_rest()),
+        TradingMode::Mock => {
+            println!("[{} | SETUP] Running against a simulated exchange", time());
+            Box::new(SimulatedExchange::new(load_simulated_balance(&config().files.mock_balance)))
+        },
+        TradingMode::Paper => {
+            println!("[{} | SETUP] Paper trading against live order books", time());
+            let file = config().files.paper_balance;
+            Box::new(SimulatedExchange::paper(load_simulated_balance(&file), &file))
+        },
     };
-    Some(ammount * val)
+    if EXCHANGE.set(Mutex::new(exchange)).is_err() || MODE.set(mode).is_err() {
+        panic!("SETUP EXCHANGE PANIC: exchange already initialised");
+    }
 }
 
-fn get_eth_value(ammount: f64) -> Option<f64> {
-    let val = {
-        let prices = CURRENT_PRICES.lock().unwrap();
-        match prices.1 {
-            Some(pr) => pr,
-            None => return None,
-        }
-    };
-    Some(ammount * val)
+fn exchange() -> MutexGuard<'static, Box<dyn Exchange>> {
+    EXCHANGE.get().expect("exchange not initialised").lock().unwrap()
 }
 
-fn cancel_all_orders() -> Result<CancelAllOrdersResponse, krakenrs::Error> {
-    let api = REST_API.lock().unwrap();
-    block_in_place(|| api.cancel_all_orders())
+/**
+ * Starting balance of the simulated exchange, 0.01 of the quote asset by default
+ */
+fn load_simulated_balance(file: &str) -> HashMap<String, Decimal> {
+    let market = config().market;
+    let balance = std::fs::read_to_string(file)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+    match balance {
+        Some(balance) => balance,
+        None => HashMap::from([
+            (market.quote_balance_key, Decimal::new(1, 2)),
+            (market.base_balance_key, Decimal::ZERO),
+        ]),
+    }
 }
 
-fn time() -> String {
-    Local::now().format("%d-%m-%Y %H:%M:%S").to_string()
+fn setup_rest() -> KrakenExchange {
+    let creds = match KrakenCredentials::load_json_file(config().files.creds) {
+        Ok(creds) => creds,
+        Err(e) => panic!("SETUP REST PANIC: {}", e),
+    };
+    let kraken_config = KrakenRestConfig { creds, ..KrakenRestConfig::default() };
+    KrakenExchange::new(kraken_config).expect("could not create kraken rest api")
 }
 
-#[tokio::main]
-async fn run_bot() {
-    let bot = Bot::from_env();
-    Command::repl(bot, answer).await;
+/**
+ * Paper trading keeps its own order history so it never
+ * overwrites the state of the live bot
+ */
+fn state_file(name: &str) -> String {
+    let path = std::path::Path::new(name);
+    match (trading_mode(), path.file_name()) {
+        (TradingMode::Paper, Some(file_name)) => path
+            .with_file_name(format!("paper_{}", file_name.to_string_lossy()))
+            .to_string_lossy()
+            .to_string(),
+        _ => name.to_string(),
+    }
 }
 
-
-#[derive(BotCommands, Clone)]
-#[command(rename_rule = "lowercase", description = "These commands are supported:")]
-enum Command {
-    #[command(description = "display this text.")]
-    Help,
-    Id,
-    Balance,
-    Price,
-    Abort,
+/**
+ * A fresh paper run starts from the last completed live hop, a live
+ * order still pending is unknown to the simulated exchange
+ */
+fn load_state() {
+    let live_file = config().files.state;
+    let file = state_file(&live_file);
+    let seeded = trading_mode() == TradingMode::Paper && !std::path::Path::new(&file).exists();
+    let source = if seeded { live_file } else { file };
+    match BotState::load(&source) {
+        Ok(state) if seeded => *STATE.lock().unwrap() = state.seed(),
+        Ok(state) => *STATE.lock().unwrap() = state,
+        Err(e) => {
+            println!("[{} | STATE] Could not load {}: {}", time(), source, e);
+            std::process::exit(1);
+        }
+    }
 }
 
-
-async fn answer(
-    bot: Bot,
-    message: Message,
-    command: Command,
-) -> ResponseResult<()> {
-
-    match command {
-        Command::Help => bot.send_message(get_report_chat_id(), Command::descriptions().to_string()).await?,
-        Command::Id => bot.send_message(get_report_chat_id(), parse_id(message)).await? ,
-        Command::Balance => bot.send_message(get_report_chat_id(), generate_balance_string()).parse_mode(ParseMode::MarkdownV2).await?,
-        Command::Price => bot.send_message(get_report_chat_id(), generate_price_string()).parse_mode(ParseMode::MarkdownV2).await?,
-        Command::Abort => bot.send_message(get_report_chat_id(), abort_order_command()).parse_mode(ParseMode::MarkdownV2).await?,
+/**
+ * Mock runs keep the journal in memory, paper runs get their own file
+ */
+fn load_journal() {
+    let file = match trading_mode() {
+        TradingMode::Mock => None,
+        _ => Some(state_file(&config().files.journal)),
     };
-    Ok(())
+    if let Err(e) = journal::open(file.as_deref()) {
+        println!("[{} | JOURNAL] Could not open the journal: {}", time(), e);
+        std::process::exit(1);
+    }
 }
 
-fn abort_order_command() -> String {
-    match cancel_all_orders() {
-        Ok(c) => (),
-        Err(e) => return format!("```Could not cancel order: {:#?}```", e),
-    };
-    load_previous_order();
-    "Successfully reverted order!".to_string()
+fn save_state() {
+    let state = STATE.lock().unwrap();
+    let file = state_file(&config().files.state);
+    if let Err(e) = state.save(&file) {
+        println!("[{} | STATE SAVE] Error saving {}: {:#?}", time(), file, e);
+    }
 }
 
-fn generate_price_string() -> String {
-    let balance_handle = thread::spawn(|| {
-        get_account_balance()
-    });
-    
-    let balance = match balance_handle.join() {
-        Ok(b_option) => match b_option {
-            Some(b) => b,
-            None => return "Could not fetch balance".to_string(),
-        },
-        Err(e) => return format!("{:#?}", e),
-    };
-    let position = get_my_position(&balance);
-    
-    let threshold_value = match calculate_threshold_value(&position) {
-        Some(val) => format!("{:.5}", val),
-        None => "Unknown".to_string(),
-    };
-
-    let gain = match calculate_gain(&position) {
-        Some(val) => {
-            let color = if val > 0. {
-                "🟢"
-            } else {
-                "🔴"
-            };
-            format!("{:.2}% {}", (val * 100.), color)
-        },
-        None => "Unknown".to_string(),
-    };
-
-    let relative_price = match get_currnet_relative_price() {
-        Some(p) => format!("{:.5}", p),
-        None => "Unknown".to_string(),
-    };
-
-    let btc_price = match get_currnet_btc_price() {
-        Some(p) => format!("{:.2}", p),
-        None => "Unknown".to_string(),
-    };
+fn get_my_position(balance: &HashMap<String, Decimal>) -> Position {
+    let market = config().market;
+    let quote_position = balance.get(&market.quote_balance_key).copied().unwrap_or(Decimal::ZERO);
+    let base_position = balance.get(&market.base_balance_key).copied().unwrap_or(Decimal::ZERO);
 
-    let eth_price = match get_currnet_eth_price() {
-        Some(p) => format!("{:.2}", p),
-        None => "Unknown".to_string(),
-    };
+    let quote_fiat = get_quote_value(quote_position).unwrap_or(Decimal::ZERO);
+    let base_fiat = get_base_value(base_position).unwrap_or(Decimal::ZERO);
 
-    format!(
-        "```\nPOSITION:   {:?}\nRELATIVE:   {}\nTHRESHOLD:  {}\nGAIN:       {}\nXXBT:       {}\nXETH:       {}\n```",
-        position,
-        relative_price,
-        threshold_value,
-        gain,
-        btc_price,
-        eth_price,
-    )    
+    if quote_fiat.is_zero() && base_fiat.is_zero() {
+        Position::None
+    } else if quote_fiat > base_fiat {
+        Position::Quote
+    } else {
+        Position::Base
+    }
 }
 
-fn generate_balance_string() -> String {
-    let balance_option = get_account_balance();
-    
-    let balance = match balance_option {
-        Some(b) => b,
-        None => return "Could not fetch balance".to_string(),
-    };
-
-    let mut out = "```".to_string();
-    for (b_key, b_val) in balance.iter() {
-        let f_val = b_val.to_f64().unwrap_or(0.);
-        let eur_val = if b_key.eq("XETH") {
-            get_eth_value(f_val).unwrap_or(0.)
-        } else if b_key.eq("XXBT") {
-            get_btc_value(f_val).unwrap_or(0.)
-        } else {
-            0.
-        };
-        let mut keyclone = b_key.clone();
-        keyclone.push_str("     ");
-        out = format!("{}\n{:.5}:  \t{:.2}€ \t({:.4})", out, keyclone, eur_val, b_val);
-    }
-    format!("{}\n```", out)
+fn get_last_trade() -> Option<(Decimal, bool)> {
+    let state = STATE.lock().unwrap();
+    state.reference_order().map(|last| (last.reference_price(), last.status != RecordStatus::Pending))
 }
 
-fn parse_id(message: Message) -> String {
-    format!("Your chat id: {:#?}", message.chat.id)
+fn get_quote_value(ammount: Decimal) -> Option<Decimal> {
+    let val = get_current_quote_price()?;
+    Some(ammount * val)
 }
 
-fn get_report_chat_id() -> ChatId {
-    let report_chat = env::var("TELEGRAM_REPORT_CHAT_ID")
-        .expect("$TELEGRAM_REPORT_CHAT_ID is not set")
-        .parse::<i64>()
-        .unwrap();
-    ChatId(report_chat)
+fn get_base_value(ammount: Decimal) -> Option<Decimal> {
+    let val = get_current_base_price()?;
+    Some(ammount * val)
 }
 
-fn notify_order_placed_telegram(order_response: &AddOrderResponse, price: &f64, gain: &f64, position: &Position) {
-    let balance_option = get_account_balance();
-    
-    let balance = match balance_option {
-        Some(b) => b,
-        None => return,
-    };
-    
-    let other_postition = match position {
-        Position::Btc => Position::Eth,
-        Position::Eth => Position::Btc,
-        Position::None => Position::None,
-    };
-    let volume_option = match position {
-        Position::Btc => balance.get("XXBT"),
-        Position::Eth => balance.get("XETH"),
-        Position::None => return,
+/**
+ * Cancels the pending order by txid and records how far it got.
+ * An order that filled in the meantime is recorded as filled.
+ */
+fn cancel_last_order(reason: &str) -> Result<RecordStatus, String> {
+    if !is_waiting_order_resolution() {
+        return Err("No pending order to abort".to_string());
+    }
+    let txid = match pending_txid() {
+        Some(txid) => txid,
+        None => {
+            // nothing to cancel by, the order has to be checked on Kraken by hand
+            journal_order(journal::Event::Aborted, None, None, format!("{}, untracked order", reason));
+            finish_last_order(RecordStatus::Cancelled, None);
+            return Ok(RecordStatus::Cancelled);
+        }
     };
 
-    let base_volume = match volume_option {
-        Some(vol) => vol.to_f64().unwrap(),
-        None => return,
+    let mut api = exchange();
+    let cancelled = api.cancel_order(&txid);
+    let order = match api.query_order(&txid) {
+        Ok(order) => order,
+        Err(e) => return Err(format!("Could not query order {}: {}", txid, e)),
     };
-
-    let volume = converted_volume(position, base_volume);
-
-    send_telegram_message(format!(
-        "Placed an order: 💰\n```\n{:?}\n```\nSummary: 📂\n```\nPRICE: {}\nGAIN: {:.3}%\nPOSITION: {:?} -> {:?}\nVOLUME: {:.5}\n```", 
-        order_response.descr.order,
-        price, 
-        (gain * 100.),
-        position,
-        other_postition,
-        volume
-    ));
-}
-
-fn notify_order_completed_telegram() {
-    send_telegram_message(format!("Last order seems to have been filled 🎉💰"));
+    drop(api);
+
+    let prior_fill = has_prior_fill();
+    let status = match (order_outcome(&order, prior_fill), cancelled) {
+        (Some(status), _) => status,
+        // cancel accepted but still on the book, cancellation is pending
+        (None, Ok(_)) => closed_outcome(&order, prior_fill),
+        (None, Err(e)) => return Err(format!("Could not cancel order {}: {}", txid, e)),
+    };
+    journal_order(journal::Event::Aborted, Some(txid), Some(&order), format!("{}, ended as {:?}", reason, status));
+    finish_last_order(status, Some(&order));
+    Ok(status)
 }
 
-
-pub fn send_telegram_message(message: String) {
-    let report_chat = env::var("TELEGRAM_REPORT_CHAT_ID")
-        .expect("$TELEGRAM_REPORT_CHAT_ID is not set")
-        .parse::<i64>()
-        .unwrap();
-
-    block_in_place( move || {
-        Handle::current().block_on(async move {
-            match Bot::from_env().send_message(
-                ChatId(report_chat),
-                message
-            ).parse_mode(ParseMode::MarkdownV2).await {
-                Ok(e) => println!("Sent message: {:?}", e),
-                Err(e) => println!("Error sending message {:#?}", e),
-            }
-        });
-    });
+fn time() -> String {
+    Local::now().format("%d-%m-%Y %H:%M:%S").to_string()
 }
 
-fn notify_stream_close() {
-    send_telegram_message(format!("Stream closed from Kraken! Will try to reconnect in 5mins. 🔧"));
+/**
+ * A manual abort never places more orders, a partial fill is kept as it is
+ */
+fn abort_order_command() -> String {
+    match cancel_last_order("/abort command") {
+        Ok(RecordStatus::PartiallyFilled) => markdown::escape(&format!(
+            "Aborted order, it was partially filled. {}",
+            handle_partial_fill(PartialFillPolicy::Accept),
+        )),
+        Ok(status) => markdown::escape(&format!("Aborted order, it ended as {:?}.", status)),
+        Err(e) => format!("```\nCould not abort order: {}\n```", e),
+    }
 }
 
-fn notify_successful_reconnect() {
-    send_telegram_message(format!("Reconnect to Kraken successful. 👍"));
-}
 
-fn abort_order() {
-    match cancel_all_orders() {
-        Ok(c) => (),
-        Err(e) => send_telegram_message(format!("```Could not revert order after timeout: {:#?}```", e)),
-    };
-    load_previous_order();
-    send_telegram_message(format!("Timeout! Successfully reverted order! 👍"))
+/**
+ * Returns false when the order could not be cancelled and is still pending
+ */
+fn abort_order() -> bool {
+    match cancel_last_order("order_abort_timeout") {
+        Ok(RecordStatus::PartiallyFilled) => {
+            let follow_up = handle_partial_fill(config().strategy.partial_fill);
+            send_telegram_message(markdown::escape(&format!("Timeout! Order was only partially filled. {}", follow_up)));
+            true
+        },
+        Ok(status) => {
+            send_telegram_message(markdown::escape(&format!("Timeout! Aborted order, it ended as {:?}. 👍", status)));
+            true
+        },
+        Err(e) => {
+            send_telegram_message(format!("```\nCould not abort order after timeout: {}\n```", e));
+            false
+        },
+    }
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+use krakenrs::ws::{BookData, BookEntry};
+use rand::Rng;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+use crate::backtest::{load_ticks, Tick};
+use crate::config::config;
+use crate::time;
+
+// a step of the random walk moves a price by at most this fraction
+const MAX_STEP: f64 = 0.001;
+// half the spread of the synthetic books, as a fraction of the mid price
+const HALF_SPREAD: f64 = 0.0005;
+
+/**
+ * Order books for --mock runs, which never connect to Kraken. Prices are
+ * replayed in a loop from `files.mock_prices` (the backtest format) when it
+ * exists, otherwise they follow a random walk.
+ */
+pub struct MockFeed {
+    recorded: Vec<Tick>,
+    next: usize,
+    // base/quote and the quote asset in fiat, the random walk
+    relative: Decimal,
+    quote_fiat: Decimal,
+}
+
+impl MockFeed {
+    pub fn load() -> Self {
+        let file = config().files.mock_prices;
+        let recorded = match load_ticks(&file) {
+            Ok(ticks) => {
+                println!("[{} | MOCK FEED] Replaying {} prices from {}", time(), ticks.len(), file);
+                ticks
+            },
+            Err(_) => {
+                println!("[{} | MOCK FEED] No {}, prices follow a random walk", time(), file);
+                vec![]
+            },
+        };
+        MockFeed {
+            recorded,
+            next: 0,
+            relative: Decimal::new(5, 2),
+            quote_fiat: Decimal::from(60000),
+        }
+    }
+
+    /**
+     * Books of the subscribed pairs at the next price
+     */
+    pub fn next_books(&mut self) -> BTreeMap<String, BookData> {
+        let (relative, base_fiat, quote_fiat) = match self.recorded.get(self.next) {
+            Some(tick) => {
+                self.next = (self.next + 1) % self.recorded.len();
+                (tick.relative, tick.base_fiat, tick.quote_fiat)
+            },
+            None => {
+                self.relative = step(self.relative);
+                self.quote_fiat = step(self.quote_fiat);
+                (self.relative, self.relative * self.quote_fiat, self.quote_fiat)
+            },
+        };
+        let market = config().market;
+        BTreeMap::from([
+            (market.pair(), book(relative)),
+            (market.base_fiat_pair(), book(base_fiat)),
+            (market.quote_fiat_pair(), book(quote_fiat)),
+        ])
+    }
+}
+
+fn step(price: Decimal) -> Decimal {
+    let change = rand::thread_rng().gen_range(-MAX_STEP..=MAX_STEP);
+    price * Decimal::from_f64(1.0 + change).unwrap_or(Decimal::ONE)
+}
+
+/**
+ * One level on each side of the mid price
+ */
+fn book(mid: Decimal) -> BookData {
+    let half_spread = mid * Decimal::from_f64(HALF_SPREAD).unwrap_or(Decimal::ZERO);
+    let (bid, ask) = ((mid - half_spread).round_dp(8), (mid + half_spread).round_dp(8));
+    BookData {
+        bid: BTreeMap::from([(bid, level(bid))]),
+        ask: BTreeMap::from([(ask, level(ask))]),
+        checksum_failed: false,
+    }
+}
+
+fn level(price: Decimal) -> BookEntry {
+    let volume = Decimal::from(10);
+    BookEntry {
+        volume,
+        timestamp: Decimal::from(chrono::Local::now().timestamp()),
+        price_str: price.to_string(),
+        volume_str: volume.to_string(),
+    }
+}
@@ -0,0 +1,251 @@
+use krakenrs::ws::{BookData, BookEntry};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::{PricingConfig, PricingPolicy};
+use crate::state::OrderSide;
+
+/**
+ * Top of one order book and the volume weighted price of each
+ * side over the subscribed depth
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookPrices {
+    pub bid: Decimal,
+    pub ask: Decimal,
+    pub mid: Decimal,
+    pub bid_vwap: Decimal,
+    pub ask_vwap: Decimal,
+}
+
+impl BookPrices {
+    /**
+     * None while either side of the book is empty
+     */
+    pub fn from_book(book: &BookData, depth: usize) -> Option<BookPrices> {
+        let bid = *book.bid.keys().next_back()?;
+        let ask = *book.ask.keys().next()?;
+        Some(BookPrices {
+            bid,
+            ask,
+            mid: (bid + ask) / Decimal::TWO,
+            bid_vwap: vwap(book.bid.iter().rev().take(depth))?,
+            ask_vwap: vwap(book.ask.iter().take(depth))?,
+        })
+    }
+}
+
+/**
+ * Why a book can't be traded on
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum BookProblem {
+    Empty,
+    Crossed { bid: Decimal, ask: Decimal },
+    // our copy no longer matches Kraken's checksum
+    ChecksumFailed,
+    Stale(Duration),
+    Disconnected,
+}
+
+impl fmt::Display for BookProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookProblem::Empty => write!(f, "has an empty side"),
+            BookProblem::Crossed { bid, ask } => write!(f, "is crossed, bid {} >= ask {}", bid, ask),
+            BookProblem::ChecksumFailed => write!(f, "failed the checksum"),
+            BookProblem::Stale(age) => write!(f, "has not changed for {}s", age.as_secs()),
+            BookProblem::Disconnected => write!(f, "is disconnected"),
+        }
+    }
+}
+
+/**
+ * Problems visible in the book itself, staleness is up to the caller
+ */
+pub fn check_book(book: &BookData) -> Option<BookProblem> {
+    if book.checksum_failed {
+        return Some(BookProblem::ChecksumFailed);
+    }
+    let (bid, ask) = match (book.bid.keys().next_back(), book.ask.keys().next()) {
+        (Some(bid), Some(ask)) => (*bid, *ask),
+        _ => return Some(BookProblem::Empty),
+    };
+    if bid >= ask {
+        return Some(BookProblem::Crossed { bid, ask });
+    }
+    None
+}
+
+/**
+ * When each book last changed. Books that haven't changed since the
+ * connection was made are timed from then.
+ */
+pub struct BookAges {
+    connected_at: Instant,
+    changed: HashMap<String, Instant>,
+}
+
+impl BookAges {
+    pub fn new(connected_at: Instant) -> Self {
+        BookAges { connected_at, changed: HashMap::new() }
+    }
+
+    pub fn changed(&mut self, pair: &str, at: Instant) {
+        self.changed.insert(pair.to_string(), at);
+    }
+
+    pub fn stale(&self, pair: &str, now: Instant, after: Duration) -> Option<BookProblem> {
+        let since = self.changed.get(pair).copied().unwrap_or(self.connected_at);
+        let age = now.saturating_duration_since(since);
+        (age > after).then_some(BookProblem::Stale(age))
+    }
+}
+
+fn vwap<'a>(levels: impl Iterator<Item = (&'a Decimal, &'a BookEntry)>) -> Option<Decimal> {
+    let mut value = Decimal::ZERO;
+    let mut volume = Decimal::ZERO;
+    for (price, entry) in levels {
+        value += price * entry.volume;
+        volume += entry.volume;
+    }
+    value.checked_div(volume)
+}
+
+/**
+ * Limit price of an order on this book, `tick` is the smallest price step of the pair.
+ * Join rests at the best price of our own side, cross goes `cross_ticks` past the
+ * best price of the other side, vwap prices at the depth weighted other side.
+ */
+pub fn order_price(side: OrderSide, book: &BookPrices, pricing: &PricingConfig, tick: Decimal) -> Decimal {
+    let ticks = tick * Decimal::from(pricing.cross_ticks);
+    match (pricing.policy, side) {
+        (PricingPolicy::Join, OrderSide::Buy) => book.bid,
+        (PricingPolicy::Join, OrderSide::Sell) => book.ask,
+        (PricingPolicy::Cross, OrderSide::Buy) => book.ask + ticks,
+        (PricingPolicy::Cross, OrderSide::Sell) => book.bid - ticks,
+        (PricingPolicy::Mid, _) => book.mid,
+        (PricingPolicy::Vwap, OrderSide::Buy) => book.ask_vwap,
+        (PricingPolicy::Vwap, OrderSide::Sell) => book.bid_vwap,
+    }
+}
+
+/**
+ * Orders priced this way rest on the book and pay the maker fee,
+ * the others take liquidity
+ */
+pub fn is_maker(policy: PricingPolicy) -> bool {
+    matches!(policy, PricingPolicy::Join | PricingPolicy::Mid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::collections::BTreeMap;
+
+    fn levels(levels: &[(Decimal, i64)]) -> BTreeMap<Decimal, BookEntry> {
+        levels
+            .iter()
+            .map(|(price, volume)| {
+                let entry = BookEntry {
+                    volume: Decimal::from(*volume),
+                    timestamp: Decimal::ZERO,
+                    price_str: price.to_string(),
+                    volume_str: volume.to_string(),
+                };
+                (*price, entry)
+            })
+            .collect()
+    }
+
+    fn book(bids: &[(Decimal, i64)], asks: &[(Decimal, i64)]) -> BookData {
+        BookData { bid: levels(bids), ask: levels(asks), checksum_failed: false }
+    }
+
+    // 0.04990 / 0.05010 on top, one more level on each side
+    fn prices() -> BookPrices {
+        let book = book(
+            &[(dec!(0.04990), 1), (dec!(0.04980), 3)],
+            &[(dec!(0.05010), 1), (dec!(0.05020), 3)],
+        );
+        BookPrices::from_book(&book, 2).unwrap()
+    }
+
+    #[test]
+    fn book_prices_weigh_the_depth_by_volume() {
+        let prices = prices();
+        assert_eq!(prices.bid, dec!(0.04990));
+        assert_eq!(prices.ask, dec!(0.05010));
+        assert_eq!(prices.mid, dec!(0.05000));
+        assert_eq!(prices.bid_vwap, dec!(0.049825));
+        assert_eq!(prices.ask_vwap, dec!(0.050175));
+        assert_eq!(BookPrices::from_book(&book(&[], &[(Decimal::ONE, 1)]), 2), None);
+    }
+
+    #[test]
+    fn order_prices_follow_the_policy() {
+        let tick = dec!(0.00001);
+        let cases = [
+            (PricingPolicy::Join, OrderSide::Buy, 0, dec!(0.04990)),
+            (PricingPolicy::Join, OrderSide::Sell, 0, dec!(0.05010)),
+            (PricingPolicy::Mid, OrderSide::Buy, 0, dec!(0.05000)),
+            (PricingPolicy::Mid, OrderSide::Sell, 0, dec!(0.05000)),
+            (PricingPolicy::Cross, OrderSide::Buy, 0, dec!(0.05010)),
+            (PricingPolicy::Cross, OrderSide::Buy, 2, dec!(0.05012)),
+            (PricingPolicy::Cross, OrderSide::Sell, 2, dec!(0.04988)),
+            (PricingPolicy::Vwap, OrderSide::Buy, 0, dec!(0.050175)),
+            (PricingPolicy::Vwap, OrderSide::Sell, 0, dec!(0.049825)),
+        ];
+        for (policy, side, cross_ticks, expected) in cases {
+            let pricing = PricingConfig { policy, cross_ticks };
+            assert_eq!(order_price(side, &prices(), &pricing, tick), expected, "{:?} {:?}", policy, side);
+        }
+    }
+
+    #[test]
+    fn cross_ticks_use_the_pair_tick_size() {
+        let pricing = PricingConfig { policy: PricingPolicy::Cross, cross_ticks: 3 };
+        assert_eq!(order_price(OrderSide::Buy, &prices(), &pricing, dec!(0.00005)), dec!(0.05025));
+        assert_eq!(order_price(OrderSide::Sell, &prices(), &pricing, dec!(0.00005)), dec!(0.04975));
+    }
+
+    #[test]
+    fn only_resting_policies_are_makers() {
+        assert!(is_maker(PricingPolicy::Join));
+        assert!(is_maker(PricingPolicy::Mid));
+        assert!(!is_maker(PricingPolicy::Cross));
+        assert!(!is_maker(PricingPolicy::Vwap));
+    }
+
+    #[test]
+    fn books_go_stale_when_they_stop_changing() {
+        let connected_at = Instant::now();
+        let after = Duration::from_secs(900);
+        let mut ages = BookAges::new(connected_at);
+        assert_eq!(ages.stale("ETH/XBT", connected_at + after, after), None);
+        let quiet = connected_at + Duration::from_secs(901);
+        assert_eq!(ages.stale("ETH/XBT", quiet, after), Some(BookProblem::Stale(Duration::from_secs(901))));
+        ages.changed("ETH/XBT", connected_at + Duration::from_secs(600));
+        assert_eq!(ages.stale("ETH/XBT", quiet, after), None);
+        assert_eq!(ages.stale("XBT/EUR", quiet, after), Some(BookProblem::Stale(Duration::from_secs(901))));
+        let later = connected_at + Duration::from_secs(1600);
+        assert_eq!(ages.stale("ETH/XBT", later, after), Some(BookProblem::Stale(Duration::from_secs(1000))));
+        assert_eq!(BookProblem::Stale(Duration::from_secs(1000)).to_string(), "has not changed for 1000s");
+    }
+
+    #[test]
+    fn crossed_and_empty_books_are_problems() {
+        let (low, high) = (dec!(0.04990), dec!(0.05010));
+        assert_eq!(check_book(&book(&[(low, 1)], &[(high, 1)])), None);
+        assert_eq!(check_book(&book(&[(high, 1)], &[(low, 1)])), Some(BookProblem::Crossed { bid: high, ask: low }));
+        assert_eq!(check_book(&book(&[(low, 1)], &[(low, 1)])), Some(BookProblem::Crossed { bid: low, ask: low }));
+        assert_eq!(check_book(&book(&[], &[(high, 1)])), Some(BookProblem::Empty));
+        assert_eq!(check_book(&book(&[(low, 1)], &[])), Some(BookProblem::Empty));
+        let mut failed = book(&[(low, 1)], &[(high, 1)]);
+        failed.checksum_failed = true;
+        assert_eq!(check_book(&failed), Some(BookProblem::ChecksumFailed));
+    }
+}
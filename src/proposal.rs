@@ -0,0 +1,48 @@
+use once_cell::sync::Lazy;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::state::OrderSide;
+use crate::strategy::Position;
+
+/**
+ * Hops waiting for a button press on Telegram, by the id in the button data.
+ * Whoever takes a proposal first decides it, a late press or the timeout
+ * finds it gone.
+ */
+static PROPOSALS: Lazy<Mutex<HashMap<u64, Proposal>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    // a /hop preview, confirmed or cancelled
+    Manual,
+    // a strategy decision in approval mode, approved or rejected
+    Approval,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Proposal {
+    pub kind: Kind,
+    pub position: Position,
+    pub gain: Decimal,
+    pub net_gain: Decimal,
+    // side, limit price and volume a /hop preview showed
+    pub order: Option<(OrderSide, Decimal, Decimal)>,
+}
+
+pub fn open(proposal: Proposal) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    PROPOSALS.lock().unwrap().insert(id, proposal);
+    id
+}
+
+pub fn take(id: u64) -> Option<Proposal> {
+    PROPOSALS.lock().unwrap().remove(&id)
+}
+
+pub fn is_open(kind: Kind) -> bool {
+    PROPOSALS.lock().unwrap().values().any(|proposal| proposal.kind == kind)
+}
@@ -0,0 +1,63 @@
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+use crate::config::ReconnectConfig;
+
+/**
+ * Reconnect schedule of one websocket. Delays grow exponentially with
+ * jitter, so restarts don't hit Kraken's connection rate limit in step.
+ */
+pub struct Reconnect {
+    attempts: u32,
+    // first failure of the current outage
+    since: Option<Instant>,
+    // successful reconnects since startup
+    count: u64,
+}
+
+impl Reconnect {
+    pub fn new() -> Self {
+        Reconnect {
+            attempts: 0,
+            since: None,
+            count: 0,
+        }
+    }
+
+    /**
+     * Delay before the next attempt, None once the retry window is over
+     */
+    pub fn next_delay(&mut self, policy: &ReconnectConfig) -> Option<Duration> {
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= Duration::from_secs(policy.window_secs) {
+            return None;
+        }
+        let exponent = self.attempts.min(31);
+        self.attempts += 1;
+        let ceiling = policy.backoff_ms.saturating_mul(1 << exponent).min(policy.backoff_max_ms);
+        // half fixed, half random
+        let delay = ceiling / 2 + rand::thread_rng().gen_range(0..=ceiling - ceiling / 2);
+        Some(Duration::from_millis(delay))
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /**
+     * How long the current outage has lasted
+     */
+    pub fn outage(&self) -> Duration {
+        self.since.map(|since| since.elapsed()).unwrap_or_default()
+    }
+
+    /**
+     * Ends the outage, returns the number of reconnects so far
+     */
+    pub fn connected(&mut self) -> u64 {
+        self.attempts = 0;
+        self.since = None;
+        self.count += 1;
+        self.count
+    }
+}
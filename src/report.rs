@@ -0,0 +1,271 @@
+use chrono::{Local, TimeZone};
+use rust_decimal::Decimal;
+
+use crate::config::{config, MarketConfig};
+use crate::journal::{self, Fill};
+use crate::state::OrderSide;
+
+/**
+ * Prices the holdings are valued at, base/quote and the quote asset in fiat
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Prices {
+    pub relative: Decimal,
+    pub quote_fiat: Option<Decimal>,
+}
+
+/**
+ * Performance of the journaled fills. The starting capital is what the
+ * first hop converted, holdings follow every fill after it.
+ */
+#[derive(Debug, Default)]
+struct Report {
+    since: i64,
+    hops: usize,
+    start_quote: Decimal,
+    start_base: Decimal,
+    start_relative: Decimal,
+    start_quote_fiat: Option<Decimal>,
+    quote: Decimal,
+    base: Decimal,
+    // hops judged against the holdings the last time the bot held that asset
+    wins: usize,
+    judged: usize,
+    avg_holding_secs: Option<i64>,
+}
+
+fn build(fills: &[Fill]) -> Option<Report> {
+    let first = fills.first()?;
+    let mut report = Report {
+        since: first.timestamp,
+        start_relative: first.price,
+        start_quote_fiat: first.fiat_price,
+        ..Report::default()
+    };
+    // the first hop is every fill up to the first change of side
+    for fill in fills.iter().take_while(|fill| fill.side == first.side) {
+        match fill.side {
+            OrderSide::Buy => report.start_quote += fill.volume * fill.price + fill.fee,
+            OrderSide::Sell => report.start_base += fill.volume,
+        }
+    }
+    report.quote = report.start_quote;
+    report.base = report.start_base;
+
+    let (mut entered_quote, mut entered_base) = match first.side {
+        OrderSide::Buy => (Some(report.start_quote), None),
+        OrderSide::Sell => (None, Some(report.start_base)),
+    };
+    let mut hop_times = Vec::new();
+    for (i, fill) in fills.iter().enumerate() {
+        match fill.side {
+            OrderSide::Buy => {
+                report.base += fill.volume;
+                report.quote -= fill.volume * fill.price + fill.fee;
+            },
+            OrderSide::Sell => {
+                report.base -= fill.volume;
+                report.quote += fill.volume * fill.price - fill.fee;
+            },
+        }
+        // remainders of a partial fill belong to the same hop
+        if fills.get(i + 1).is_some_and(|next| next.side == fill.side) {
+            continue;
+        }
+        report.hops += 1;
+        hop_times.push(fill.timestamp);
+        let (held, entered) = match fill.side {
+            OrderSide::Buy => (report.base, &mut entered_base),
+            OrderSide::Sell => (report.quote, &mut entered_quote),
+        };
+        if let Some(before) = *entered {
+            report.judged += 1;
+            if held > before {
+                report.wins += 1;
+            }
+        }
+        *entered = Some(held);
+    }
+    if hop_times.len() > 1 {
+        let held: i64 = hop_times.windows(2).map(|pair| pair[1] - pair[0]).sum();
+        report.avg_holding_secs = Some(held / (hop_times.len() as i64 - 1));
+    }
+    Some(report)
+}
+
+/**
+ * Change of a value against a reference as a signed percentage
+ */
+pub fn relative(value: Decimal, reference: Decimal) -> String {
+    match value.checked_div(reference) {
+        Some(ratio) => format!("{:+.2}%", ((ratio - Decimal::ONE) * Decimal::ONE_HUNDRED).round_dp(2)),
+        None => "Unknown".to_string(),
+    }
+}
+
+fn duration(secs: i64) -> String {
+    format!("{}d {:02}h {:02}m", secs / 86400, secs % 86400 / 3600, secs % 3600 / 60)
+}
+
+fn format_report(report: &Report, prices: Prices) -> String {
+    let market: MarketConfig = config().market;
+    let (quote, base, fiat) = (&market.quote, &market.base, &market.fiat);
+    let since = match Local.timestamp_opt(report.since, 0).single() {
+        Some(since) => since.format("%d-%m-%Y %H:%M:%S").to_string(),
+        None => report.since.to_string(),
+    };
+
+    let start_value_quote = report.start_quote + report.start_base * report.start_relative;
+    let start_value_base = report.start_quote.checked_div(report.start_relative).unwrap_or(Decimal::ZERO) + report.start_base;
+    let value_quote = report.quote + report.base * prices.relative;
+    let value_base = report.quote.checked_div(prices.relative).unwrap_or(Decimal::ZERO) + report.base;
+    let hold_value_quote = report.start_quote + report.start_base * prices.relative;
+    let fiat_return = match (report.start_quote_fiat, prices.quote_fiat) {
+        (Some(start), Some(now)) => relative(value_quote * now, start_value_quote * start),
+        _ => "Unknown".to_string(),
+    };
+    let win_rate = match report.judged {
+        0 => "Unknown".to_string(),
+        judged => format!(
+            "{:.1}% ({} of {})",
+            (Decimal::from(report.wins * 100) / Decimal::from(judged)).round_dp(1),
+            report.wins,
+            judged,
+        ),
+    };
+
+    [
+        format!("SINCE:       {}", since),
+        format!("HOPS:        {}", report.hops),
+        format!("WIN RATE:    {}", win_rate),
+        format!("AVG HOLDING: {}", report.avg_holding_secs.map(duration).unwrap_or_else(|| "Unknown".to_string())),
+        format!("START:       {:.5} {} / {:.5} {}", report.start_quote.round_dp(5), quote, report.start_base.round_dp(5), base),
+        format!("NOW:         {:.5} {} / {:.5} {}", report.quote.round_dp(5), quote, report.base.round_dp(5), base),
+        format!("{:<13}{}", format!("RETURN {}:", quote), relative(value_quote, start_value_quote)),
+        format!("{:<13}{}", format!("RETURN {}:", base), relative(value_base, start_value_base)),
+        format!("{:<13}{}", format!("RETURN {}:", fiat), fiat_return),
+        format!("VS HOLD:     {}", relative(value_quote, hold_value_quote)),
+        format!("VALUED AT:   {:.5} {}", prices.relative.round_dp(5), market.pair()),
+    ]
+    .join("\n")
+}
+
+/**
+ * Report of the journal valued at the given prices, falling back to
+ * the latest journaled prices
+ */
+pub fn generate(prices: Option<Prices>) -> Result<String, String> {
+    let fills = journal::fills().map_err(|e| format!("Could not read the journal: {}", e))?;
+    let report = match build(&fills) {
+        Some(report) => report,
+        None => return Err("No fills journaled yet".to_string()),
+    };
+    let prices = match prices {
+        Some(prices) => prices,
+        None => match journal::last_prices().map_err(|e| format!("Could not read the journal: {}", e))? {
+            Some((relative, quote_fiat)) => Prices { relative, quote_fiat },
+            None => return Err("No prices journaled yet".to_string()),
+        },
+    };
+    Ok(format_report(&report, prices))
+}
+
+/**
+ * kraken_trading_bot report [--paper]
+ * Without live books the holdings are valued at the latest journaled prices.
+ */
+pub fn run() {
+    println!("REPORT {}", config().market.pair());
+    match generate(None) {
+        Ok(report) => println!("{}", report),
+        Err(e) => println!("[REPORT] {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn fill(timestamp: i64, side: OrderSide, price: Decimal, volume: Decimal, fee: Decimal) -> Fill {
+        Fill {
+            timestamp,
+            side,
+            price,
+            volume,
+            fee,
+            fiat_price: Some(dec!(60000)),
+        }
+    }
+
+    #[test]
+    fn nothing_to_report_without_fills() {
+        assert!(build(&[]).is_none());
+    }
+
+    #[test]
+    fn single_hop() {
+        let report = build(&[fill(100, OrderSide::Buy, dec!(0.05), dec!(1), dec!(0.0001))]).unwrap();
+        assert_eq!(report.since, 100);
+        assert_eq!(report.hops, 1);
+        assert_eq!((report.start_quote, report.start_base), (dec!(0.0501), Decimal::ZERO));
+        assert_eq!((report.quote, report.base), (Decimal::ZERO, dec!(1)));
+        assert_eq!(report.start_quote_fiat, Some(dec!(60000)));
+        assert_eq!((report.wins, report.judged), (0, 0));
+        assert_eq!(report.avg_holding_secs, None);
+    }
+
+    #[test]
+    fn first_hop_buy() {
+        let report = build(&[
+            fill(0, OrderSide::Buy, dec!(0.05), dec!(1), dec!(0.0001)),
+            fill(3600, OrderSide::Sell, dec!(0.06), dec!(1), dec!(0.0001)),
+            fill(10800, OrderSide::Buy, dec!(0.0599), dec!(0.99), dec!(0.0001)),
+        ]).unwrap();
+        assert_eq!(report.hops, 3);
+        assert_eq!(report.start_relative, dec!(0.05));
+        assert_eq!((report.start_quote, report.start_base), (dec!(0.0501), Decimal::ZERO));
+        assert_eq!(report.quote, dec!(0.0599) - dec!(0.059301) - dec!(0.0001));
+        assert_eq!(report.base, dec!(0.99));
+        // more quote than the start after selling, less base than before after buying back
+        assert_eq!((report.wins, report.judged), (1, 2));
+        assert_eq!(report.avg_holding_secs, Some(5400));
+    }
+
+    #[test]
+    fn first_hop_sell() {
+        let report = build(&[
+            fill(0, OrderSide::Sell, dec!(0.05), dec!(2), dec!(0)),
+            fill(600, OrderSide::Buy, dec!(0.0475), dec!(2.1), dec!(0)),
+        ]).unwrap();
+        assert_eq!(report.hops, 2);
+        assert_eq!((report.start_quote, report.start_base), (Decimal::ZERO, dec!(2)));
+        assert_eq!(report.quote, dec!(0.1) - dec!(0.09975));
+        assert_eq!(report.base, dec!(2.1));
+        assert_eq!((report.wins, report.judged), (1, 1));
+        assert_eq!(report.avg_holding_secs, Some(600));
+    }
+
+    #[test]
+    fn remainder_of_a_partial_fill_is_the_same_hop() {
+        let report = build(&[
+            fill(0, OrderSide::Buy, dec!(0.05), dec!(0.4), dec!(0)),
+            fill(60, OrderSide::Buy, dec!(0.051), dec!(0.6), dec!(0)),
+            fill(3600, OrderSide::Sell, dec!(0.055), dec!(0.5), dec!(0)),
+            fill(3700, OrderSide::Sell, dec!(0.054), dec!(0.5), dec!(0)),
+        ]).unwrap();
+        assert_eq!(report.hops, 2);
+        assert_eq!(report.start_quote, dec!(0.0506));
+        assert_eq!((report.quote, report.base), (dec!(0.0545), Decimal::ZERO));
+        assert_eq!((report.wins, report.judged), (1, 1));
+        // held from the last fill of the first hop to the last fill of the second
+        assert_eq!(report.avg_holding_secs, Some(3640));
+    }
+
+    #[test]
+    fn relative_change() {
+        assert_eq!(relative(dec!(1.1), dec!(1)), "+10.00%");
+        assert_eq!(relative(dec!(0.95), dec!(1)), "-5.00%");
+        assert_eq!(relative(dec!(1), Decimal::ZERO), "Unknown");
+    }
+}
@@ -0,0 +1,423 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/**
+ * Bump when the layout of BotState changes and add a step to `migrate`
+ */
+pub const STATE_VERSION: u32 = 2;
+
+// files used before the state document existed, (price, completed) tuples
+const LEGACY_LAST_ORDER: &str = "last.json";
+const LEGACY_LAST_COMPLETED_ORDER: &str = "last_completed.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordStatus {
+    // placed, waiting to be filled
+    Pending,
+    Filled,
+    // closed or cancelled with only part of the volume executed
+    PartiallyFilled,
+    Cancelled,
+    Expired,
+}
+
+/**
+ * Volume executed by the orders of one hop and its average price
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Fill {
+    pub volume: Decimal,
+    pub avg_price: Decimal,
+}
+
+impl Fill {
+    pub fn merge(self, other: Fill) -> Fill {
+        let volume = self.volume + other.volume;
+        match (self.volume * self.avg_price + other.volume * other.avg_price).checked_div(volume) {
+            Some(avg_price) => Fill { volume, avg_price },
+            None => self,
+        }
+    }
+}
+
+/**
+ * An order placed by the bot. Orders recovered from the legacy files
+ * and the bootstrap reference price have no txid, side or volume.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderRecord {
+    pub txid: Option<String>,
+    pub side: Option<OrderSide>,
+    pub price: Decimal,
+    pub volume: Option<Decimal>,
+    // unix seconds
+    pub timestamp: i64,
+    pub status: RecordStatus,
+    // as reported by the exchange once the order resolved
+    #[serde(default)]
+    pub executed_volume: Option<Decimal>,
+    #[serde(default)]
+    pub avg_price: Option<Decimal>,
+    // what earlier orders of the same hop filled, set when the remainder of a partial fill is re-placed
+    #[serde(default)]
+    pub prior_fill: Option<Fill>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BotState {
+    pub version: u32,
+    // the last order placed, None before the first price is known
+    pub last_order: Option<OrderRecord>,
+    // the order we fall back to when the last order is aborted,
+    // or a partial fill that was accepted as the completed hop
+    pub last_completed: Option<OrderRecord>,
+    // set by /pause, no hops are placed until /resume
+    #[serde(default)]
+    pub paused: bool,
+}
+
+impl Default for BotState {
+    fn default() -> Self {
+        BotState {
+            version: STATE_VERSION,
+            last_order: None,
+            last_completed: None,
+            paused: false,
+        }
+    }
+}
+
+impl OrderRecord {
+    fn from_legacy((price, completed): (Decimal, bool), timestamp: i64) -> Self {
+        OrderRecord {
+            txid: None,
+            side: None,
+            price,
+            volume: None,
+            timestamp,
+            status: if completed { RecordStatus::Filled } else { RecordStatus::Pending },
+            executed_volume: None,
+            avg_price: None,
+            prior_fill: None,
+        }
+    }
+
+    /**
+     * Everything executed for this hop so far, earlier orders included
+     */
+    pub fn total_fill(&self) -> Fill {
+        let own = Fill {
+            volume: self.executed_volume.unwrap_or(Decimal::ZERO),
+            avg_price: self.avg_price.unwrap_or(self.price),
+        };
+        match self.prior_fill {
+            Some(prior) => prior.merge(own),
+            None => own,
+        }
+    }
+
+    /**
+     * Volume of the whole hop, this order plus what earlier orders filled
+     */
+    pub fn hop_volume(&self) -> Decimal {
+        self.volume.unwrap_or(Decimal::ZERO) + self.prior_fill.map_or(Decimal::ZERO, |prior| prior.volume)
+    }
+
+    /**
+     * Price gains are measured from. A hop that took several orders
+     * or was accepted half way uses its average fill price.
+     */
+    pub fn reference_price(&self) -> Decimal {
+        let fill = self.total_fill();
+        if (self.prior_fill.is_none() && self.status != RecordStatus::PartiallyFilled) || fill.volume.is_zero() {
+            return self.price;
+        }
+        fill.avg_price
+    }
+}
+
+impl BotState {
+    /**
+     * The order gains are measured against: the last order if it filled,
+     * the last completed one if it was cancelled, expired or partially filled
+     */
+    pub fn reference_order(&self) -> Option<&OrderRecord> {
+        match &self.last_order {
+            Some(last) if last.status == RecordStatus::Pending || last.status == RecordStatus::Filled => Some(last),
+            _ => self.last_completed.as_ref(),
+        }
+    }
+
+    /**
+     * A state that starts from this one's last completed hop. Orders still
+     * in flight belong to the exchange this state came from.
+     */
+    pub fn seed(&self) -> BotState {
+        let completed = match &self.last_order {
+            Some(last) if last.status == RecordStatus::Filled => Some(last.clone()),
+            _ => self.last_completed.clone(),
+        };
+        BotState {
+            last_order: completed.clone(),
+            last_completed: completed,
+            ..BotState::default()
+        }
+    }
+
+    /**
+     * Loads the state document. Without one the legacy files next to it are
+     * migrated, and without those the bot starts from an empty state.
+     */
+    pub fn load(file: &str) -> Result<BotState, Error> {
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return BotState::from_legacy_files(file),
+            Err(e) => return Err(e),
+        };
+        let document: serde_json::Value = serde_json::from_str(&content)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{} is not valid json: {}", file, e)))?;
+        let version = document.get("version").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+        if version > STATE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} has version {}, this build only understands up to {}", file, version, STATE_VERSION),
+            ));
+        }
+        migrate(document, version)
+    }
+
+    /**
+     * Writes to a temporary file first so a crash never leaves half a state behind
+     */
+    pub fn save(&self, file: &str) -> Result<(), Error> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        let tmp = format!("{}.tmp", file);
+        {
+            let mut out = fs::File::create(&tmp)?;
+            out.write_all(content.as_bytes())?;
+            out.sync_all()?;
+        }
+        fs::rename(&tmp, file)
+    }
+
+    fn from_legacy_files(file: &str) -> Result<BotState, Error> {
+        let legacy_last = sibling(file, LEGACY_LAST_ORDER);
+        let legacy_completed = sibling(file, LEGACY_LAST_COMPLETED_ORDER);
+        if !legacy_last.exists() {
+            return Ok(BotState::default());
+        }
+
+        let timestamp = fs::metadata(&legacy_last)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|since| since.as_secs() as i64)
+            .unwrap_or(0);
+        let last_order = read_legacy(&legacy_last)?;
+        let last_completed = if legacy_completed.exists() {
+            read_legacy(&legacy_completed)?
+        } else {
+            last_order
+        };
+
+        let state = BotState {
+            version: STATE_VERSION,
+            last_order: Some(OrderRecord::from_legacy(last_order, timestamp)),
+            last_completed: Some(OrderRecord::from_legacy(last_completed, timestamp)),
+            paused: false,
+        };
+        state.save(file)?;
+        for legacy in [legacy_last, legacy_completed] {
+            if legacy.exists() {
+                fs::rename(&legacy, legacy.with_extension("json.migrated"))?;
+            }
+        }
+        Ok(state)
+    }
+}
+
+/**
+ * Brings a document of an older version up to STATE_VERSION
+ */
+fn migrate(document: serde_json::Value, version: u32) -> Result<BotState, Error> {
+    match version {
+        // prices and volumes became decimal strings, numbers still parse
+        1 => {
+            let mut document = document;
+            document["version"] = STATE_VERSION.into();
+            migrate(document, 2)
+        },
+        STATE_VERSION => serde_json::from_value(document)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("malformed state: {}", e))),
+        other => Err(Error::new(ErrorKind::InvalidData, format!("unknown state version {}", other))),
+    }
+}
+
+fn sibling(file: &str, name: &str) -> PathBuf {
+    Path::new(file).with_file_name(name)
+}
+
+fn read_legacy(path: &Path) -> Result<(Decimal, bool), Error> {
+    let content = fs::read_to_string(path)?;
+    serde_json::from_str(&content)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{} is not a (price, completed) tuple: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    // an empty directory per test, tests run in parallel
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kraken_trading_bot_state_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn record(price: Decimal, status: RecordStatus) -> OrderRecord {
+        OrderRecord {
+            txid: Some("TX".to_string()),
+            side: Some(OrderSide::Buy),
+            price,
+            volume: Some(dec!(2)),
+            timestamp: 0,
+            status,
+            executed_volume: None,
+            avg_price: None,
+            prior_fill: None,
+        }
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let document = serde_json::json!({
+            "version": 1,
+            "last_order": {
+                "txid": null, "side": null, "price": 0.05, "volume": null,
+                "timestamp": 10, "status": "filled"
+            },
+            "last_completed": null
+        });
+        let state = migrate(document, 1).unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert!(!state.paused);
+        let last = state.last_order.unwrap();
+        assert_eq!(last.price, dec!(0.05));
+        assert_eq!(last.status, RecordStatus::Filled);
+        assert_eq!(last.prior_fill, None);
+    }
+
+    #[test]
+    fn refuses_unknown_versions() {
+        assert!(migrate(serde_json::json!({}), 0).is_err());
+    }
+
+    #[test]
+    fn first_run_starts_empty() {
+        let dir = scratch_dir("first_run");
+        let file = dir.join("state.json");
+        let state = BotState::load(file.to_str().unwrap()).unwrap();
+        assert_eq!(state, BotState::default());
+        assert!(!file.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn migrates_legacy_tuples() {
+        let dir = scratch_dir("legacy");
+        fs::write(dir.join(LEGACY_LAST_ORDER), "[0.051,false]").unwrap();
+        fs::write(dir.join(LEGACY_LAST_COMPLETED_ORDER), "[0.05,true]").unwrap();
+        let file = dir.join("state.json");
+
+        let state = BotState::load(file.to_str().unwrap()).unwrap();
+        let last = state.last_order.as_ref().unwrap();
+        assert_eq!(last.price, dec!(0.051));
+        assert_eq!(last.status, RecordStatus::Pending);
+        let completed = state.last_completed.as_ref().unwrap();
+        assert_eq!(completed.price, dec!(0.05));
+        assert_eq!(completed.status, RecordStatus::Filled);
+
+        assert!(!dir.join(LEGACY_LAST_ORDER).exists());
+        assert!(dir.join("last.json.migrated").exists());
+        assert!(dir.join("last_completed.json.migrated").exists());
+        // the next start reads the state document
+        assert_eq!(BotState::load(file.to_str().unwrap()).unwrap(), state);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn plain_fill_is_the_reference() {
+        let order = record(dec!(0.05), RecordStatus::Filled);
+        assert_eq!(order.reference_price(), dec!(0.05));
+        assert_eq!(order.hop_volume(), dec!(2));
+    }
+
+    #[test]
+    fn partial_fill_and_remainder_average() {
+        // 1 of 2 filled at 0.05, the remainder of 1 re-placed and filled at 0.06
+        let remainder = OrderRecord {
+            price: dec!(0.06),
+            volume: Some(dec!(1)),
+            status: RecordStatus::Filled,
+            executed_volume: Some(dec!(1)),
+            avg_price: Some(dec!(0.06)),
+            prior_fill: Some(Fill { volume: dec!(1), avg_price: dec!(0.05) }),
+            ..record(dec!(0.06), RecordStatus::Filled)
+        };
+        assert_eq!(remainder.total_fill(), Fill { volume: dec!(2), avg_price: dec!(0.055) });
+        assert_eq!(remainder.hop_volume(), dec!(2));
+        assert_eq!(remainder.reference_price(), dec!(0.055));
+    }
+
+    #[test]
+    fn accepted_partial_fill_uses_its_average() {
+        let partial = OrderRecord {
+            executed_volume: Some(dec!(0.5)),
+            avg_price: Some(dec!(0.049)),
+            ..record(dec!(0.05), RecordStatus::PartiallyFilled)
+        };
+        assert_eq!(partial.total_fill(), Fill { volume: dec!(0.5), avg_price: dec!(0.049) });
+        assert_eq!(partial.reference_price(), dec!(0.049));
+    }
+
+    #[test]
+    fn seeds_drop_the_pending_order() {
+        let state = BotState {
+            last_order: Some(record(dec!(0.06), RecordStatus::Pending)),
+            last_completed: Some(record(dec!(0.05), RecordStatus::Filled)),
+            paused: true,
+            ..BotState::default()
+        };
+        let seed = state.seed();
+        assert_eq!(seed.last_order, state.last_completed);
+        assert_eq!(seed.last_completed, state.last_completed);
+        assert!(!seed.paused);
+
+        let filled = BotState { last_order: Some(record(dec!(0.06), RecordStatus::Filled)), ..state };
+        assert_eq!(filled.seed().reference_order().unwrap().price, dec!(0.06));
+    }
+
+    #[test]
+    fn cancelled_order_falls_back_to_the_last_completed() {
+        let state = BotState {
+            last_order: Some(record(dec!(0.06), RecordStatus::Cancelled)),
+            last_completed: Some(record(dec!(0.05), RecordStatus::Filled)),
+            ..BotState::default()
+        };
+        assert_eq!(state.reference_order().unwrap().price, dec!(0.05));
+    }
+}
@@ -0,0 +1,172 @@
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+
+use crate::config::{MarketConfig, StrategyConfig};
+
+/**
+ * Which side of the base/quote pair (e.g. ETH/XBT) we hold
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Position {
+    Base,
+    Quote,
+    None,
+}
+
+impl Position {
+    pub fn other(&self) -> Position {
+        match self {
+            Position::Base => Position::Quote,
+            Position::Quote => Position::Base,
+            Position::None => Position::None,
+        }
+    }
+
+    pub fn name(&self, market: &MarketConfig) -> String {
+        match self {
+            Position::Base => market.base.clone(),
+            Position::Quote => market.quote.clone(),
+            Position::None => "None".to_string(),
+        }
+    }
+}
+
+/**
+ * Gain of hopping now relative to the price of the last order.
+ * Holding the quote asset we gain when base/quote drops,
+ * holding the base asset when it rises.
+ */
+pub fn gain(position: &Position, current_value: Decimal, last_value: Decimal) -> Option<Decimal> {
+    let gain_ratio = current_value.checked_div(last_value)? - Decimal::ONE;
+
+    match position {
+        Position::Quote => Some(-gain_ratio),
+        Position::Base => Some(gain_ratio),
+        Position::None => None,
+    }
+}
+
+/**
+ * Gain left after the fee paid on the order that got us into the
+ * position and the fee of hopping back
+ */
+pub fn net_gain(gross: Decimal, fee: Decimal) -> Decimal {
+    (Decimal::ONE + gross) * (Decimal::ONE - fee) * (Decimal::ONE - fee) - Decimal::ONE
+}
+
+/**
+ * Gross gain needed to be left with `net` after fees
+ */
+fn gross_gain_needed(net: Decimal, fee: Decimal) -> Decimal {
+    (Decimal::ONE + net) / ((Decimal::ONE - fee) * (Decimal::ONE - fee)) - Decimal::ONE
+}
+
+/**
+ * Thresholds are configured as floats. One that can't be
+ * represented is never reached.
+ */
+fn threshold(value: f64) -> Decimal {
+    Decimal::from_f64(value).unwrap_or(Decimal::MAX)
+}
+
+/**
+ * Net gain needed to hop out of the position
+ */
+pub fn hop_threshold(position: &Position, thresholds: &StrategyConfig) -> Option<Decimal> {
+    match position {
+        Position::Quote => Some(threshold(thresholds.to_base)),
+        Position::Base => Some(threshold(thresholds.to_quote)),
+        Position::None => None,
+    }
+}
+
+/**
+ * Thresholds are compared against the net gain
+ */
+pub fn should_hop(position: &Position, gain: Decimal, thresholds: &StrategyConfig) -> bool {
+    hop_threshold(position, thresholds).is_some_and(|threshold| gain > threshold)
+}
+
+/**
+ * base/quote execution price at which the strategy will hop
+ */
+pub fn threshold_value(position: &Position, last_value: Decimal, thresholds: &StrategyConfig, fee: Decimal) -> Option<Decimal> {
+    match position {
+        Position::Quote => Some(last_value * (Decimal::ONE - gross_gain_needed(threshold(thresholds.to_base), fee))),
+        Position::Base => Some(last_value * (Decimal::ONE + gross_gain_needed(threshold(thresholds.to_quote), fee))),
+        Position::None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn thresholds() -> StrategyConfig {
+        StrategyConfig { to_quote: 0.03, to_base: 0.02, ..StrategyConfig::default() }
+    }
+
+    #[test]
+    fn quote_gains_when_the_price_drops() {
+        assert_eq!(gain(&Position::Quote, dec!(0.049), dec!(0.05)), Some(dec!(0.02)));
+        assert_eq!(gain(&Position::Quote, dec!(0.051), dec!(0.05)), Some(dec!(-0.02)));
+    }
+
+    #[test]
+    fn base_gains_when_the_price_rises() {
+        assert_eq!(gain(&Position::Base, dec!(0.051), dec!(0.05)), Some(dec!(0.02)));
+        assert_eq!(gain(&Position::Base, dec!(0.049), dec!(0.05)), Some(dec!(-0.02)));
+        assert_eq!(gain(&Position::None, dec!(0.051), dec!(0.05)), None);
+    }
+
+    #[test]
+    fn fees_are_paid_twice() {
+        assert_eq!(net_gain(Decimal::ZERO, Decimal::ZERO), Decimal::ZERO);
+        assert_eq!(net_gain(Decimal::ZERO, dec!(0.01)), dec!(-0.0199));
+        assert!(net_gain(dec!(0.02), dec!(0.0026)) < dec!(0.02));
+        let fee = dec!(0.0016);
+        let net = net_gain(gross_gain_needed(dec!(0.02), fee), fee);
+        assert!((net - dec!(0.02)).abs() < dec!(0.000000000001));
+    }
+
+    #[test]
+    fn threshold_value_is_on_the_right_side() {
+        let last = dec!(0.05);
+        let fee = dec!(0.0016);
+        let to_base = threshold_value(&Position::Quote, last, &thresholds(), fee).unwrap();
+        let to_quote = threshold_value(&Position::Base, last, &thresholds(), fee).unwrap();
+        // more than the configured gain is needed once fees are paid
+        assert!(to_base < last * dec!(0.98));
+        assert!(to_quote > last * dec!(1.03));
+        assert_eq!(threshold_value(&Position::None, last, &thresholds(), fee), None);
+    }
+
+    #[test]
+    fn should_hop_agrees_with_threshold_value() {
+        let last = dec!(0.05);
+        let fee = dec!(0.0016);
+        let nudge = dec!(0.0000001);
+        for position in [Position::Quote, Position::Base] {
+            let boundary = threshold_value(&position, last, &thresholds(), fee).unwrap();
+            // a price just past the boundary is a hop, just short of it is not
+            let (past, short) = match position {
+                Position::Quote => (boundary - nudge, boundary + nudge),
+                _ => (boundary + nudge, boundary - nudge),
+            };
+            let hops = |price| should_hop(&position, net_gain(gain(&position, price, last).unwrap(), fee), &thresholds());
+            assert!(hops(past), "{:?} should hop at {}", position, past);
+            assert!(!hops(short), "{:?} should not hop at {}", position, short);
+            let at_boundary = net_gain(gain(&position, boundary, last).unwrap(), fee);
+            let threshold = hop_threshold(&position, &thresholds()).unwrap();
+            assert!((at_boundary - threshold).abs() < dec!(0.000000000001));
+        }
+    }
+
+    #[test]
+    fn thresholds_match_the_position() {
+        assert_eq!(hop_threshold(&Position::Quote, &thresholds()), Some(dec!(0.02)));
+        assert_eq!(hop_threshold(&Position::Base, &thresholds()), Some(dec!(0.03)));
+        assert!(!should_hop(&Position::None, dec!(1), &thresholds()));
+    }
+}
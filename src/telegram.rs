@@ -0,0 +1,667 @@
+use krakenrs::AddOrderResponse;
+use once_cell::sync::OnceCell;
+use rust_decimal::Decimal;
+use std::env;
+use std::time::Duration;
+use teloxide::dispatching::{Dispatcher, HandlerExt, UpdateFilterExt};
+use teloxide::error_handlers::LoggingErrorHandler;
+use teloxide::payloads::{AnswerCallbackQuerySetters, SendMessageSetters};
+use teloxide::requests::{Requester, ResponseResult};
+use teloxide::types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId, ParseMode, Update, User};
+use teloxide::utils::command::BotCommands;
+use teloxide::utils::markdown;
+use teloxide::{dptree, Bot};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::sleep;
+
+use crate::config::{self, config};
+use crate::engine::{blocking, HopRequest, OrderCommand};
+use crate::exchange::OrderState;
+use crate::journal::Event;
+use crate::proposal::{self, Kind, Proposal};
+use crate::reconnect::Reconnect;
+use crate::report;
+use crate::state::RecordStatus;
+use crate::strategy::Position;
+use crate::{
+    book_problems, calculate_gain, calculate_net_gain, calculate_threshold_value, converted_volume, get_account_balance,
+    get_base_value, get_current_base_price, get_current_quote_price, get_current_relative_price, get_my_position,
+    get_execution_price, get_quote_value, hop_fee, hop_order, is_waiting_order_resolution, journal_hop, set_paused, time,
+    trading_paused,
+};
+
+/**
+ * Outgoing messages, sent to the report chat by the notifier task
+ */
+static NOTIFIER: OnceCell<mpsc::UnboundedSender<Outgoing>> = OnceCell::new();
+
+pub enum Outgoing {
+    Message(String),
+    // a hop proposal in approval mode, sent with Approve and Reject buttons
+    Proposal(u64, String),
+}
+
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "These commands are supported:")]
+pub enum Command {
+    #[command(description = "display this text.")]
+    Help,
+    Id,
+    Balance,
+    Price,
+    Abort,
+    Report,
+    Pause,
+    Resume,
+    #[command(description = "show the strategy values.")]
+    Config,
+    #[command(description = "change a strategy value, e.g. /set to_eth 0.025")]
+    Set(String),
+    #[command(description = "preview a hop right now and confirm it.")]
+    Hop,
+}
+
+pub fn setup_notifier() -> mpsc::UnboundedReceiver<Outgoing> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    if NOTIFIER.set(sender).is_err() {
+        panic!("SETUP NOTIFIER PANIC: notifier already initialised");
+    }
+    receiver
+}
+
+/**
+ * Queues a MarkdownV2 message for the report chat, never blocks
+ */
+pub fn send_telegram_message(message: String) {
+    queue(Outgoing::Message(message));
+}
+
+/**
+ * Proposes a hop the strategy decided on, it is only placed once approved
+ * within telegram.approval_timeout_secs
+ */
+pub fn send_proposal(proposal: Proposal) {
+    let market = config().market;
+    let text = format!(
+        "Hop proposal, approve to place it ❓\n```\nPOSITION: {} -> {}\nPRICE:    {}\nGAIN:     {}\nNET GAIN: {}\n```",
+        proposal.position.name(&market),
+        proposal.position.other().name(&market),
+        get_execution_price(&proposal.position).map_or("Unknown".to_string(), |price| format!("{:.5}", price.round_dp(5))),
+        format_gain(Some(proposal.gain)),
+        format_gain(Some(proposal.net_gain)),
+    );
+    queue(Outgoing::Proposal(proposal::open(proposal), text));
+}
+
+fn queue(outgoing: Outgoing) {
+    match NOTIFIER.get() {
+        Some(notifier) => {
+            if notifier.send(outgoing).is_err() {
+                println!("[{} | TELEGRAM] Notifier stopped, message dropped", time());
+            }
+        },
+        None => match outgoing {
+            Outgoing::Message(message) => println!("[{} | TELEGRAM] Notifier not set up: {}", time(), message),
+            // nobody could answer it
+            Outgoing::Proposal(id, message) => {
+                proposal::take(id);
+                println!("[{} | TELEGRAM] Notifier not set up, proposal dropped: {}", time(), message)
+            },
+        },
+    }
+}
+
+/**
+ * Sends queued messages until stopped, then whatever is still queued
+ */
+pub async fn run_notifier(bot: Bot, mut messages: mpsc::UnboundedReceiver<Outgoing>, mut stop: oneshot::Receiver<()>) {
+    loop {
+        tokio::select! {
+            message = messages.recv() => match message {
+                Some(message) => deliver(&bot, message).await,
+                None => return,
+            },
+            _ = &mut stop => break,
+        }
+    }
+    while let Ok(message) = messages.try_recv() {
+        deliver(&bot, message).await;
+    }
+}
+
+async fn deliver(bot: &Bot, outgoing: Outgoing) {
+    let chat = get_report_chat_id();
+    let sent = match outgoing {
+        Outgoing::Message(message) => bot.send_message(chat, message).parse_mode(ParseMode::MarkdownV2).await,
+        Outgoing::Proposal(id, message) => {
+            let buttons = InlineKeyboardMarkup::new([[
+                InlineKeyboardButton::callback("Approve", format!("approve:{}", id)),
+                InlineKeyboardButton::callback("Reject", format!("reject:{}", id)),
+            ]]);
+            let sent = bot.send_message(chat, message).parse_mode(ParseMode::MarkdownV2).reply_markup(buttons).await;
+            // a proposal that could not be sent still expires
+            let timeout = Duration::from_secs(config().telegram.approval_timeout_secs);
+            expire_later(bot.clone(), chat, sent.as_ref().ok().map(|sent| sent.id), id, timeout);
+            sent
+        },
+    };
+    match sent {
+        Ok(m) => println!("Sent message: {:?}", m),
+        Err(e) => println!("Error sending message {:#?}", e),
+    }
+}
+
+/**
+ * Answers commands until shutdown, letting the handlers that are running finish
+ */
+pub async fn run_commands(bot: Bot, orders: mpsc::Sender<OrderCommand>, mut shutdown: watch::Receiver<bool>) {
+    let handler = dptree::entry()
+        .branch(Update::filter_message().filter_command::<Command>().endpoint(answer))
+        .branch(Update::filter_callback_query().endpoint(answer_button));
+    let bot_check = bot.clone();
+    let mut dispatcher = Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![orders])
+        .default_handler(|_| async {})
+        .error_handler(LoggingErrorHandler::with_custom_text("An error from the update listener"))
+        .build();
+    // the dispatcher panics if Telegram can't be reached when it starts
+    let mut reconnect = Reconnect::new();
+    while let Err(e) = bot_check.get_me().await {
+        let policy = config().reconnect;
+        let delay = reconnect.next_delay(&policy).unwrap_or(Duration::from_millis(policy.backoff_max_ms));
+        println!("[{} | TELEGRAM] Could not reach Telegram: {}, retrying in {:?}", time(), e, delay);
+        tokio::select! {
+            _ = shutdown.changed() => return,
+            _ = sleep(delay) => (),
+        }
+    }
+    let token = dispatcher.shutdown_token();
+    let dispatching = dispatcher.dispatch();
+    tokio::pin!(dispatching);
+    tokio::select! {
+        _ = &mut dispatching => return,
+        _ = shutdown.changed() => (),
+    }
+    if token.shutdown().is_ok() {
+        dispatching.await;
+    }
+}
+
+async fn answer(
+    bot: Bot,
+    message: Message,
+    command: Command,
+    orders: mpsc::Sender<OrderCommand>,
+) -> ResponseResult<()> {
+    if !is_authorized(message.chat.id, message.from()) {
+        println!(
+            "[{} | TELEGRAM] Rejected {:?} from chat {} user {}",
+            time(),
+            message.text().unwrap_or_default(),
+            message.chat.id,
+            message.from().map(|user| user.id.to_string()).unwrap_or_else(|| "unknown".to_string()),
+        );
+        return Ok(());
+    }
+
+    let chat = message.chat.id;
+    match command {
+        Command::Help => bot.send_message(chat, Command::descriptions().to_string()).await?,
+        Command::Id => bot.send_message(chat, parse_id(message)).await? ,
+        Command::Balance => bot.send_message(chat, blocking(generate_balance_string).await).parse_mode(ParseMode::MarkdownV2).await?,
+        Command::Price => bot.send_message(chat, blocking(generate_price_string).await).parse_mode(ParseMode::MarkdownV2).await?,
+        Command::Abort => bot.send_message(chat, request_abort(&orders).await).parse_mode(ParseMode::MarkdownV2).await?,
+        Command::Report => bot.send_message(chat, blocking(generate_report_string).await).parse_mode(ParseMode::MarkdownV2).await?,
+        Command::Pause => bot.send_message(chat, blocking(|| pause_string(true)).await).await?,
+        Command::Resume => bot.send_message(chat, blocking(|| pause_string(false)).await).await?,
+        Command::Config => bot.send_message(chat, generate_config_string()).parse_mode(ParseMode::MarkdownV2).await?,
+        Command::Set(args) => bot.send_message(chat, blocking(move || set_config_string(&args)).await).parse_mode(ParseMode::MarkdownV2).await?,
+        Command::Hop => send_hop_preview(&bot, chat).await?,
+    };
+    Ok(())
+}
+
+/**
+ * Commands come from the report chat or an allowed chat, and from an
+ * allowed user when the user list is set
+ */
+fn is_authorized(chat: ChatId, user: Option<&User>) -> bool {
+    let telegram = config().telegram;
+    let chat_allowed = chat == get_report_chat_id() || telegram.allowed_chats.contains(&chat.0);
+    let user_allowed = telegram.allowed_users.is_empty()
+        || user.is_some_and(|user| telegram.allowed_users.contains(&(user.id.0 as i64)));
+    chat_allowed && user_allowed
+}
+
+fn user_name(user: &User) -> String {
+    match &user.username {
+        Some(username) => format!("@{} ({})", username, user.id),
+        None => format!("{} ({})", user.first_name, user.id),
+    }
+}
+
+/**
+ * The order is only placed once Confirm is pressed within
+ * telegram.confirm_timeout_secs, the buttons go away after that
+ */
+async fn send_hop_preview(bot: &Bot, chat: ChatId) -> ResponseResult<Message> {
+    let (proposal, preview) = match blocking(generate_hop_preview).await {
+        Ok(preview) => preview,
+        Err(e) => return bot.send_message(chat, markdown::escape(&e)).parse_mode(ParseMode::MarkdownV2).await,
+    };
+    let id = proposal::open(proposal);
+    let buttons = InlineKeyboardMarkup::new([[
+        InlineKeyboardButton::callback("Confirm", format!("confirm:{}", id)),
+        InlineKeyboardButton::callback("Cancel", format!("cancel:{}", id)),
+    ]]);
+    let sent = bot.send_message(chat, preview).parse_mode(ParseMode::MarkdownV2).reply_markup(buttons).await?;
+
+    let timeout = Duration::from_secs(config().telegram.confirm_timeout_secs);
+    expire_later(bot.clone(), chat, Some(sent.id), id, timeout);
+    Ok(sent)
+}
+
+/**
+ * Drops the proposal if nobody answered it in time and removes its buttons
+ */
+fn expire_later(bot: Bot, chat: ChatId, message_id: Option<MessageId>, id: u64, timeout: Duration) {
+    tokio::spawn(async move {
+        sleep(timeout).await;
+        let proposal = match proposal::take(id) {
+            Some(proposal) => proposal,
+            None => return,
+        };
+        if proposal.kind == Kind::Approval {
+            println!("[{} | TELEGRAM] Hop proposal expired", time());
+            let reason = format!("nobody answered within {:?}", timeout);
+            blocking(move || journal_hop(Event::ProposalExpired, &proposal.position, proposal.gain, proposal.net_gain, reason)).await;
+        }
+        if let Some(message_id) = message_id {
+            let _ = bot.edit_message_reply_markup(chat, message_id).await;
+            let _ = bot.send_message(chat, "Hop expired, nothing was placed.").reply_to_message_id(message_id).await;
+        }
+    });
+}
+
+/**
+ * What a hop would place right now, with the gain it would realise
+ */
+fn generate_hop_preview() -> Result<(Proposal, String), String> {
+    if is_waiting_order_resolution() {
+        return Err("An order is still pending, /abort it first.".to_string());
+    }
+    if trading_paused() {
+        return Err("Trading is paused, /resume first.".to_string());
+    }
+    if let Some(problems) = book_problems() {
+        return Err(format!("Trading is suspended: {}", problems));
+    }
+    let balance = get_account_balance().map_err(|e| format!("Could not fetch balance: {}", e))?;
+    let position = get_my_position(&balance);
+    let (gain, net_gain) = match (calculate_gain(&position), calculate_net_gain(&position)) {
+        (Some(gain), Some(net_gain)) => (gain, net_gain),
+        _ => return Err("No gain yet, prices or the reference price are missing.".to_string()),
+    };
+    let (side, price, volume) = hop_order(&position, &balance).map_err(|e| format!("Could not prepare the order: {}", e))?;
+    let fee = volume * price * hop_fee();
+
+    let market = config().market;
+    let preview = format!(
+        "Hop now? ❓\n```\nPOSITION: {} -> {}\nSIDE:     {}\nVOLUME:   {:.5} {}\nPRICE:    {:.5}\nGAIN:     {}\nNET GAIN: {}\nFEE:      ~{:.8} {}\n```",
+        position.name(&market),
+        position.other().name(&market),
+        format!("{:?}", side).to_lowercase(),
+        volume.round_dp(5),
+        market.base,
+        price.round_dp(5),
+        format_gain(Some(gain)),
+        format_gain(Some(net_gain)),
+        fee.round_dp(8),
+        market.quote,
+    );
+    Ok((Proposal { kind: Kind::Manual, position, gain, net_gain, order: Some((side, price, volume)) }, preview))
+}
+
+/**
+ * Confirm and Cancel of a hop preview, Approve and Reject of a proposal.
+ * The outcome is posted as a reply, the buttons are removed.
+ */
+async fn answer_button(bot: Bot, query: CallbackQuery, orders: mpsc::Sender<OrderCommand>) -> ResponseResult<()> {
+    let message = match &query.message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    let (chat, message_id) = (message.chat.id, message.id);
+    if !is_authorized(chat, Some(&query.from)) {
+        println!(
+            "[{} | TELEGRAM] Rejected button {:?} from chat {} user {}",
+            time(),
+            query.data.as_deref().unwrap_or_default(),
+            chat,
+            query.from.id,
+        );
+        bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    }
+
+    let pressed = query.data.as_deref()
+        .and_then(|data| data.split_once(':'))
+        .and_then(|(action, id)| id.parse::<u64>().ok().map(|id| (action, id)));
+    let (action, proposal) = match pressed {
+        Some((action, id)) => (action, proposal::take(id)),
+        None => return Ok(()),
+    };
+    let proposal = match proposal {
+        Some(proposal) => proposal,
+        None => {
+            bot.answer_callback_query(query.id).text("Expired or already answered.").await?;
+            return Ok(());
+        },
+    };
+    bot.answer_callback_query(query.id.clone()).await?;
+    bot.edit_message_reply_markup(chat, message_id).await?;
+
+    let responder = user_name(&query.from);
+    let accepted = matches!(action, "confirm" | "approve");
+    let journaled = match (proposal.kind, accepted) {
+        (Kind::Manual, _) => None,
+        (Kind::Approval, true) => Some((Event::Approved, format!("approved by {}", responder))),
+        (Kind::Approval, false) => Some((Event::Rejected, format!("rejected by {}", responder))),
+    };
+    if let Some((event, reason)) = journaled {
+        println!("[{} | TELEGRAM] Hop {}", time(), reason);
+        // the gain when it was answered, the market moved since the proposal
+        blocking(move || {
+            let position = proposal.position;
+            let gain = calculate_gain(&position).unwrap_or(proposal.gain);
+            let net_gain = calculate_net_gain(&position).unwrap_or(proposal.net_gain);
+            journal_hop(event, &position, gain, net_gain, reason)
+        }).await;
+    }
+    let outcome = if accepted {
+        let reason = match proposal.kind {
+            Kind::Manual => format!("manual /hop confirmed by {}", responder),
+            Kind::Approval => format!("approved by {}", responder),
+        };
+        request_hop(&orders, proposal, reason).await
+    } else {
+        markdown::escape(&format!("Hop {} by {}.", if proposal.kind == Kind::Manual { "cancelled" } else { "rejected" }, responder))
+    };
+    bot.send_message(chat, outcome).parse_mode(ParseMode::MarkdownV2).reply_to_message_id(message_id).await?;
+    Ok(())
+}
+
+/**
+ * Hops go through the order manager like the strategy's, it has the final say
+ */
+async fn request_hop(orders: &mpsc::Sender<OrderCommand>, proposal: Proposal, reason: String) -> String {
+    let (reply, response) = oneshot::channel();
+    let command = OrderCommand::Hop(HopRequest {
+        position: proposal.position,
+        gain: proposal.gain,
+        net_gain: proposal.net_gain,
+        reason,
+        order: proposal.order,
+        // a forced /hop ignores the thresholds, an approved one still has to clear them
+        recheck: proposal.kind == Kind::Approval,
+        reply: Some(reply),
+    });
+    if orders.send(command).await.is_err() {
+        return markdown::escape("The order manager is not running.");
+    }
+    response.await.unwrap_or_else(|_| markdown::escape("The order manager stopped before placing the hop."))
+}
+
+/**
+ * Aborts go through the order manager so they never race a hop
+ */
+async fn request_abort(orders: &mpsc::Sender<OrderCommand>) -> String {
+    let (reply, response) = oneshot::channel();
+    if orders.send(OrderCommand::Abort(reply)).await.is_err() {
+        return markdown::escape("The order manager is not running.");
+    }
+    response.await.unwrap_or_else(|_| markdown::escape("The order manager stopped before aborting."))
+}
+
+/**
+ * Pausing only stops new hops, the pending order is still followed
+ */
+fn pause_string(paused: bool) -> String {
+    match (set_paused(paused), paused) {
+        (true, true) => "Trading paused. Prices and the pending order are still followed, /resume to hop again.",
+        (false, true) => "Trading is already paused.",
+        (true, false) => "Trading resumed.",
+        (false, false) => "Trading is not paused.",
+    }.to_string()
+}
+
+fn generate_config_string() -> String {
+    let strategy = toml::to_string(&config().strategy).unwrap_or_else(|e| e.to_string());
+    format!("```\n[strategy]\n{}```", markdown::escape_code(&strategy))
+}
+
+/**
+ * Takes effect on the next strategy evaluation and is written to the config file
+ */
+fn set_config_string(args: &str) -> String {
+    let (key, value) = match args.split_once(char::is_whitespace) {
+        Some((key, value)) if !value.trim().is_empty() => (key, value.trim()),
+        _ => return markdown::escape("Usage: /set <key> <value>, e.g. /set to_eth 0.025"),
+    };
+    match config::set_live(key, value) {
+        Ok((key, old, new)) => {
+            println!("[{} | CONFIG] {} changed from {} to {}", time(), key, old, new);
+            markdown::escape(&format!("{} = {} (was {}), saved to the config file.", key, new, old))
+        },
+        Err(e) => markdown::escape(&format!("Not changed: {}", e)),
+    }
+}
+
+/**
+ * Valued at the live books, or at the latest journaled prices without them
+ */
+fn generate_report_string() -> String {
+    let prices = get_current_relative_price().map(|relative| report::Prices {
+        relative,
+        quote_fiat: get_current_quote_price(),
+    });
+    match report::generate(prices) {
+        Ok(report) => format!("```\n{}\n```", markdown::escape_code(&report)),
+        Err(e) => markdown::escape(&e),
+    }
+}
+
+fn generate_price_string() -> String {
+    let balance = match get_account_balance() {
+        Ok(b) => b,
+        Err(_) => return "Could not fetch balance".to_string(),
+    };
+    let position = get_my_position(&balance);
+
+    let threshold_value = match calculate_threshold_value(&position) {
+        Some(val) => format!("{:.5}", val),
+        None => "Unknown".to_string(),
+    };
+
+    let gain = format_gain(calculate_gain(&position));
+    let net_gain = format_gain(calculate_net_gain(&position));
+    let fee = format!("{:.2}%", (hop_fee() * Decimal::ONE_HUNDRED).round_dp(2));
+
+    let relative_price = match get_current_relative_price() {
+        Some(p) => format!("{:.5}", p),
+        None => "Unknown".to_string(),
+    };
+
+    let quote_price = match get_current_quote_price() {
+        Some(p) => format!("{:.2}", p),
+        None => "Unknown".to_string(),
+    };
+
+    let base_price = match get_current_base_price() {
+        Some(p) => format!("{:.2}", p),
+        None => "Unknown".to_string(),
+    };
+
+    let books = match book_problems() {
+        Some(problems) => format!("suspended, {}", problems),
+        None => "OK".to_string(),
+    };
+    let trading = if trading_paused() {
+        "paused"
+    } else if config().strategy.approval {
+        "enabled, hops need approval"
+    } else {
+        "enabled"
+    };
+
+    let market = config().market;
+    format!(
+        "```\nPOSITION:   {}\nRELATIVE:   {}\nTHRESHOLD:  {}\nGAIN:       {}\nNET GAIN:   {}\nFEE:        {}\n{:<12}{}\n{:<12}{}\nBOOKS:      {}\nTRADING:    {}\n```",
+        position.name(&market),
+        relative_price,
+        threshold_value,
+        gain,
+        net_gain,
+        fee,
+        format!("{}:", market.quote_fiat_pair()),
+        quote_price,
+        format!("{}:", market.base_fiat_pair()),
+        base_price,
+        markdown::escape_code(&books),
+        trading,
+    )
+}
+
+fn format_gain(gain: Option<Decimal>) -> String {
+    match gain {
+        Some(val) => {
+            let color = if val.is_sign_positive() && !val.is_zero() {
+                "🟢"
+            } else {
+                "🔴"
+            };
+            format!("{:.2}% {}", (val * Decimal::ONE_HUNDRED).round_dp(2), color)
+        },
+        None => "Unknown".to_string(),
+    }
+}
+
+fn generate_balance_string() -> String {
+    let balance_option = get_account_balance().ok();
+
+    let balance = match balance_option {
+        Some(b) => b,
+        None => return "Could not fetch balance".to_string(),
+    };
+
+    let market = config().market;
+    let mut out = "```".to_string();
+    for (b_key, b_val) in balance.iter() {
+        let fiat_val = if b_key.eq(&market.base_balance_key) {
+            get_base_value(*b_val).unwrap_or(Decimal::ZERO)
+        } else if b_key.eq(&market.quote_balance_key) {
+            get_quote_value(*b_val).unwrap_or(Decimal::ZERO)
+        } else {
+            Decimal::ZERO
+        };
+        let mut keyclone = b_key.clone();
+        keyclone.push_str("     ");
+        out = format!("{}\n{:.5}:  \t{:.2} {} \t({:.4})", out, keyclone, fiat_val, market.fiat, b_val);
+    }
+    format!("{}\n```", out)
+}
+
+fn parse_id(message: Message) -> String {
+    format!("Your chat id: {:#?}", message.chat.id)
+}
+
+fn get_report_chat_id() -> ChatId {
+    let report_chat = env::var("TELEGRAM_REPORT_CHAT_ID")
+        .expect("$TELEGRAM_REPORT_CHAT_ID is not set")
+        .parse::<i64>()
+        .unwrap();
+    ChatId(report_chat)
+}
+
+/**
+ * Fetches the balance for the summary, call from a blocking task
+ */
+pub fn notify_order_placed_telegram(order_response: &AddOrderResponse, price: &Decimal, gain: &Decimal, net_gain: &Decimal, position: &Position) {
+    let balance_option = get_account_balance().ok();
+
+    let balance = match balance_option {
+        Some(b) => b,
+        None => return,
+    };
+
+    let market = config().market;
+    let other_postition = position.other();
+    let volume_option = match position {
+        Position::Quote => balance.get(&market.quote_balance_key),
+        Position::Base => balance.get(&market.base_balance_key),
+        Position::None => return,
+    };
+
+    let base_volume = match volume_option {
+        Some(vol) => *vol,
+        None => return,
+    };
+
+    let volume = converted_volume(position, base_volume, *price);
+
+    send_telegram_message(format!(
+        "Placed an order: 💰\n```\n{:?}\n```\nSummary: 📂\n```\nPRICE: {}\nGAIN: {:.3}%\nNET GAIN: {:.3}%\nPOSITION: {} -> {}\nVOLUME: {:.5}\n```",
+        order_response.descr.order,
+        price,
+        (gain * Decimal::ONE_HUNDRED),
+        (net_gain * Decimal::ONE_HUNDRED),
+        position.name(&market),
+        other_postition.name(&market),
+        volume
+    ));
+}
+
+pub fn notify_order_resolved_telegram(status: RecordStatus, order: &OrderState, follow_up: &str) {
+    let message = match status {
+        RecordStatus::Filled => format!(
+            "Order {} filled: {} at {}, fee {} 🎉💰",
+            order.txid, order.volume_executed, order.avg_price, order.fee
+        ),
+        RecordStatus::PartiallyFilled => format!(
+            "Order {} only partially filled ({} of {}) at {} ⚠️ {}",
+            order.txid, order.volume_executed, order.volume, order.avg_price, follow_up
+        ),
+        RecordStatus::Cancelled => format!("Order {} was cancelled outside of the bot. Gains are measured from the last completed order. 🔧", order.txid),
+        RecordStatus::Expired => format!("Order {} expired. Gains are measured from the last completed order. 🔧", order.txid),
+        RecordStatus::Pending => return,
+    };
+    send_telegram_message(markdown::escape(&message));
+}
+
+pub fn notify_stream_close(reason: &str, delay: Duration) {
+    send_telegram_message(markdown::escape(&format!(
+        "Stream from Kraken {}! Trading is suspended, reconnecting in {}s. 🔧",
+        reason, delay.as_secs()
+    )));
+}
+
+pub fn notify_reconnect_failed(name: &str, attempt: u32, error: &str, delay: Duration) {
+    send_telegram_message(markdown::escape(&format!(
+        "{} reconnect attempt {} failed: {}. Next attempt in {}s, giving up {}s after the first failure. 🔧",
+        name, attempt, error, delay.as_secs(), config().reconnect.window_secs
+    )));
+}
+
+pub fn notify_reconnect_gave_up(name: &str, reconnect: &Reconnect) {
+    send_telegram_message(markdown::escape(&format!(
+        "❌ {} could not reconnect in {} attempts over {}s, stopping the bot.",
+        name, reconnect.attempts(), reconnect.outage().as_secs()
+    )));
+}
+
+pub fn notify_successful_reconnect(outage: Duration, count: u64) {
+    send_telegram_message(markdown::escape(&format!(
+        "Reconnect to Kraken successful after {}s, reconnect #{} since start. 👍",
+        outage.as_secs(), count
+    )));
+}